@@ -0,0 +1,187 @@
+use std::path::Path;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, PipelineLayout, RenderPass, RenderPipeline, TextureView};
+use crate::engine::Engine;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::uniforms::depth_pass_bind_group_layout;
+
+/// Atmosphere of a planet lit by the sun of the scene, with Rayleigh scattering by air molecules
+/// and Mie scattering by aerosols. Lengths are in world units and scattering coefficients per world unit.
+/// Flat terrains are treated as a small part of a very large planet, see `Atmosphere::for_flat_terrain`.
+#[derive(Debug, Copy, Clone)]
+pub struct Atmosphere {
+    pub planet_center: Vector3<f32>,
+    pub planet_radius: f32,
+    /// thickness of the atmosphere above the ground
+    pub atmosphere_height: f32,
+    pub rayleigh_scattering: [f32; 3],
+    /// altitude at which the density of the air is divided by e
+    pub rayleigh_scale_height: f32,
+    pub mie_scattering: f32,
+    pub mie_scale_height: f32,
+    /// Henyey-Greenstein asymmetry of the Mie scattering, close to 1 for a bright halo around the sun
+    pub mie_anisotropy: f32,
+    /// multiplies the sun light of the scene
+    pub sun_intensity: f32,
+}
+
+impl Atmosphere {
+    /// Earth-like atmosphere around the planet made by `new_procedural_sphere` with the same radius,
+    /// thickened so it is visible from space.
+    pub fn for_planet(planet_center: Vector3<f32>, planet_radius: f32) -> Atmosphere {
+        let atmosphere_height = 0.15 * planet_radius;
+        let rayleigh_scale_height = 0.2 * atmosphere_height;
+        let mie_scale_height = 0.05 * atmosphere_height;
+        Atmosphere {
+            planet_center,
+            planet_radius,
+            atmosphere_height,
+            // optical depths of the Earth atmosphere at the zenith
+            rayleigh_scattering: [0.046 / rayleigh_scale_height, 0.108 / rayleigh_scale_height, 0.265 / rayleigh_scale_height],
+            rayleigh_scale_height,
+            mie_scattering: 0.025 / mie_scale_height,
+            mie_scale_height,
+            mie_anisotropy: 0.76,
+            sun_intensity: 20.0,
+        }
+    }
+
+    /// Sky dome above a flat terrain at y = 0, using the Earth atmosphere with a world unit of 10 meters.
+    pub fn for_flat_terrain() -> Atmosphere {
+        let planet_radius = 636_000.0;
+        Atmosphere {
+            planet_center: Vector3::new(0.0, -planet_radius, 0.0),
+            planet_radius,
+            atmosphere_height: 10_000.0,
+            rayleigh_scattering: [5.8e-5, 13.5e-5, 33.1e-5],
+            rayleigh_scale_height: 800.0,
+            mie_scattering: 21e-5,
+            mie_scale_height: 120.0,
+            mie_anisotropy: 0.76,
+            sun_intensity: 20.0,
+        }
+    }
+}
+
+/// Uniforms of `atmosphere.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct AtmosphereUniforms {
+    planet_center: [f32; 3],
+    planet_radius: f32,
+    rayleigh_scattering: [f32; 3],
+    atmosphere_radius: f32,
+    rayleigh_scale_height: f32,
+    mie_scattering: f32,
+    mie_scale_height: f32,
+    mie_anisotropy: f32,
+    sun_intensity: f32,
+    _padding: [f32; 3],
+}
+
+/// Fullscreen pass drawing the sky and the scattering between the camera and the meshes,
+/// reading the depth buffer of the main pass and blended over it.
+pub struct AtmosphereRenderer {
+    uniform_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Option<RenderPipeline>,
+}
+
+impl AtmosphereRenderer {
+    pub fn new(engine: &Engine) -> AtmosphereRenderer {
+        let uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Atmosphere Uniform Buffer"),
+            contents: cast_slice(&[AtmosphereUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = depth_pass_bind_group_layout(&engine.device, "Atmosphere Bind Group Layout");
+
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Atmosphere Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut renderer = AtmosphereRenderer {
+            uniform_buffer,
+            bind_group_layout,
+            pipeline_layout,
+            pipeline: None,
+        };
+        if let Err(e) = renderer.recompile(engine) {
+            eprintln!("Could not create shader \"atmosphere\", the sky will not be drawn: {}", e);
+        }
+        renderer
+    }
+
+    /// Rebuilds the pipeline, keeping the previous one on errors.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        let settings = PipelineSettings {
+            depth_format: None,
+            multisampled: false,
+            // the shader outputs the scattered light and the transmittance of the atmosphere in alpha
+            blend: wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::SrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            },
+            ..Default::default()
+        };
+        let (_, pipeline) = create_pipeline("atmosphere", &preprocessor, &self.pipeline_layout, &[], &settings, engine)?;
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    pub(crate) fn update(&self, atmosphere: &Atmosphere, engine: &Engine) {
+        let uniforms = AtmosphereUniforms {
+            planet_center: atmosphere.planet_center.into(),
+            planet_radius: atmosphere.planet_radius,
+            rayleigh_scattering: atmosphere.rayleigh_scattering,
+            atmosphere_radius: atmosphere.planet_radius + atmosphere.atmosphere_height,
+            rayleigh_scale_height: atmosphere.rayleigh_scale_height,
+            mie_scattering: atmosphere.mie_scattering,
+            mie_scale_height: atmosphere.mie_scale_height,
+            mie_anisotropy: atmosphere.mie_anisotropy,
+            sun_intensity: atmosphere.sun_intensity,
+            _padding: [0.0; 3],
+        };
+        engine.queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniforms]));
+    }
+
+    /// Bind group reading `depth_view`, the depth buffer of the frame.
+    pub(crate) fn create_bind_group(&self, depth_view: &TextureView, engine: &Engine) -> BindGroup {
+        engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+            ],
+            label: Some("Atmosphere Bind Group"),
+        })
+    }
+
+    /// Draws the atmosphere, group 0 being bound to the scene bind group.
+    pub(crate) fn draw<'a>(&'a self, bind_group: &'a BindGroup, render_pass: &mut RenderPass<'a>) {
+        if let Some(pipeline) = &self.pipeline {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::Path;
+use std::rc::Rc;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, PipelineLayout, RenderPass, RenderPipeline};
+use crate::engine::Engine;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::texture::Cubemap;
+
+/// What is seen behind the meshes. Colors are linear, like the HDR colors of the scene.
+#[derive(Clone)]
+pub enum Background {
+    Color([f32; 3]),
+    /// vertical gradient from `bottom`, looking straight down, to `top`, looking straight up
+    Gradient { top: [f32; 3], bottom: [f32; 3] },
+    /// cubemap around the camera, its colors scaled by `intensity`
+    Skybox { cubemap: Rc<Cubemap>, intensity: f32 },
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color([0.1, 0.1, 0.2])
+    }
+}
+
+impl Background {
+    /// Color the main pass is cleared with, the gradient and the skybox being drawn over it.
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self {
+            Background::Color([r, g, b]) => wgpu::Color { r: *r as f64, g: *g as f64, b: *b as f64, a: 1.0 },
+            _ => wgpu::Color::BLACK,
+        }
+    }
+
+    /// Average color of the background, that distant meshes fade into with the default fog color.
+    pub fn average_color(&self) -> [f32; 3] {
+        match self {
+            Background::Color(color) => *color,
+            Background::Gradient { top, bottom } => [0, 1, 2].map(|i| (top[i] + bottom[i]) * 0.5),
+            Background::Skybox { cubemap, intensity } => cubemap.average_color.map(|channel| channel * intensity),
+        }
+    }
+}
+
+/// Uniforms of `background.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BackgroundUniforms {
+    top: [f32; 3],
+    /// 0 solid color, 1 gradient, 2 skybox
+    mode: u32,
+    bottom: [f32; 3],
+    intensity: f32,
+}
+
+/// Fullscreen draw of the gradient or the skybox at the start of the main pass, the solid color being the clear color.
+pub struct BackgroundRenderer {
+    uniform_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Option<RenderPipeline>,
+    /// black cube bound when the background is not a skybox
+    default_cubemap: Cubemap,
+    /// cubemap of `bind_group`, None for `default_cubemap`
+    skybox: Option<Rc<Cubemap>>,
+    bind_group: BindGroup,
+    mode: u32,
+}
+
+impl BackgroundRenderer {
+    pub fn new(engine: &Engine) -> BackgroundRenderer {
+        let uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background Uniform Buffer"),
+            contents: cast_slice(&[BackgroundUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Background Bind Group Layout"),
+        });
+
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Background Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let default_cubemap = Cubemap::from_color([0.0; 3], engine);
+        let bind_group = create_bind_group(&bind_group_layout, &uniform_buffer, &default_cubemap, engine);
+
+        let mut renderer = BackgroundRenderer {
+            uniform_buffer,
+            bind_group_layout,
+            pipeline_layout,
+            pipeline: None,
+            default_cubemap,
+            skybox: None,
+            bind_group,
+            mode: 0,
+        };
+        if let Err(e) = renderer.recompile(engine) {
+            eprintln!("Could not create shader \"background\", only solid backgrounds will be drawn: {}", e);
+        }
+        renderer
+    }
+
+    /// Rebuilds the pipeline, keeping the previous one on errors.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        // drawn first in the main pass, behind everything
+        let settings = PipelineSettings {
+            depth_write_enabled: false,
+            ..Default::default()
+        };
+        let (_, pipeline) = create_pipeline("background", &preprocessor, &self.pipeline_layout, &[], &settings, engine)?;
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    /// Uploads the settings of `background`, binding its cubemap when it changed.
+    pub(crate) fn update(&mut self, background: &Background, engine: &Engine) {
+        let mut uniforms = BackgroundUniforms::zeroed();
+        let skybox = match background {
+            Background::Color(_) => None,
+            Background::Gradient { top, bottom } => {
+                uniforms.mode = 1;
+                uniforms.top = *top;
+                uniforms.bottom = *bottom;
+                None
+            }
+            Background::Skybox { cubemap, intensity } => {
+                uniforms.mode = 2;
+                uniforms.intensity = *intensity;
+                Some(cubemap)
+            }
+        };
+        self.mode = uniforms.mode;
+        engine.queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniforms]));
+
+        if skybox.map(Rc::as_ptr) != self.skybox.as_ref().map(Rc::as_ptr) {
+            let cubemap = skybox.map(Rc::as_ref).unwrap_or(&self.default_cubemap);
+            self.bind_group = create_bind_group(&self.bind_group_layout, &self.uniform_buffer, cubemap, engine);
+            self.skybox = skybox.cloned();
+        }
+    }
+
+    /// Draws the gradient or the skybox, group 0 being bound to the scene bind group.
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let (Some(pipeline), true) = (&self.pipeline, self.mode != 0) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(1, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+fn create_bind_group(layout: &BindGroupLayout, uniform_buffer: &Buffer, cubemap: &Cubemap, engine: &Engine) -> BindGroup {
+    engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&cubemap.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&cubemap.sampler),
+            },
+        ],
+        label: Some("Background Bind Group"),
+    })
+}
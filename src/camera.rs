@@ -5,6 +5,7 @@ use cgmath::{EuclideanSpace, Matrix4, perspective, Point3, Rad, Vector3};
 use crate::transform::Transform;
 use crate::engine::OPENGL_TO_WGPU_MATRIX;
 
+#[derive(Clone)]
 pub struct BasicCamera {
     pub transform: Transform,
     pub aspect_ratio: f32,
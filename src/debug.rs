@@ -0,0 +1,430 @@
+use std::mem;
+use std::path::Path;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use wgpu::{Buffer, PipelineLayout, RenderPass, RenderPipeline};
+use crate::camera::BasicCamera;
+use crate::engine::Engine;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::mesh::Mesh;
+use crate::shader::ShaderPreprocessor;
+
+pub const VERTEX_NORMAL_COLOR: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+pub const FACE_NORMAL_COLOR: [f32; 4] = [1.0, 0.2, 1.0, 1.0];
+pub const BOUNDING_BOX_COLOR: [f32; 4] = [0.2, 1.0, 0.4, 1.0];
+pub const GRID_COLOR: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl DebugVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0=>Float32x3, 1=>Float32x4];
+    pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Debug geometry drawn for a mesh, all disabled by default.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MeshDebugOptions {
+    pub vertex_normals: bool,
+    pub face_normals: bool,
+    pub bounding_box: bool,
+}
+
+/// Text drawn with `DebugRenderer::text3d`, turned into lines facing the camera when uploaded.
+struct DebugText {
+    text: String,
+    position: Vector3<f32>,
+    height: f32,
+    color: [f32; 4],
+}
+
+/// Immediate-mode debug drawing in world space, accessible with `Scene::debug`.
+/// Lines, points, boxes, spheres, arrows and text are collected during the update of the scene,
+/// drawn at the end of the render pass and cleared every frame, so they must be submitted again every frame.
+/// It also draws mesh normals and bounding boxes (see `MeshDebugOptions`) and the world axes and grid.
+pub struct DebugRenderer {
+    pub show_axes: bool,
+    pub show_grid: bool,
+    /// side length of the grid, centered on the origin in the xz plane
+    pub grid_size: f32,
+    pub grid_divisions: u32,
+    /// length of the normal lines, in world units
+    pub normal_length: f32,
+    line_vertices: Vec<DebugVertex>,
+    texts: Vec<DebugText>,
+    line_buffer: Buffer,
+    line_buffer_capacity: usize,
+    pipeline_layout: PipelineLayout,
+    line_pipeline: Option<RenderPipeline>,
+}
+
+impl DebugRenderer {
+    pub fn new(engine: &Engine) -> DebugRenderer {
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Lines Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene],
+            push_constant_ranges: &[],
+        });
+
+        let line_buffer_capacity = 1024;
+        let line_buffer = create_vertex_buffer(line_buffer_capacity, engine);
+
+        let mut debug_renderer = DebugRenderer {
+            show_axes: false,
+            show_grid: false,
+            grid_size: 10.0,
+            grid_divisions: 10,
+            normal_length: 0.1,
+            line_vertices: Vec::new(),
+            texts: Vec::new(),
+            line_buffer,
+            line_buffer_capacity,
+            pipeline_layout,
+            line_pipeline: None,
+        };
+        debug_renderer.recompile(engine);
+
+        debug_renderer
+    }
+
+    /// Rebuilds the pipeline, printing the error and keeping the previous one on failure.
+    pub fn recompile(&mut self, engine: &Engine) {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+
+        let settings = PipelineSettings {
+            topology: wgpu::PrimitiveTopology::LineList,
+            depth_write_enabled: false,
+            // drawn over the post-processed image
+            color_format: Some(engine.surface_format),
+            multisampled: false,
+            ..Default::default()
+        };
+        match create_pipeline("debug_lines", &preprocessor, &self.pipeline_layout, &[DebugVertex::desc()], &settings, engine) {
+            Ok((_, pipeline)) => self.line_pipeline = Some(pipeline),
+            Err(e) => eprintln!("Could not create the debug line pipeline: {}", e),
+        }
+    }
+
+    pub fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 4]) {
+        self.line_vertices.push(DebugVertex { position: a.into(), color });
+        self.line_vertices.push(DebugVertex { position: b.into(), color });
+    }
+
+    /// Draws a point as a small cross of `size` world units.
+    pub fn point(&mut self, position: Vector3<f32>, size: f32, color: [f32; 4]) {
+        let half_size = size / 2.0;
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()] {
+            self.line(position - axis * half_size, position + axis * half_size, color);
+        }
+    }
+
+    /// Draws an axis-aligned box given its minimum and maximum corners.
+    pub fn aabb(&mut self, min: Vector3<f32>, max: Vector3<f32>, color: [f32; 4]) {
+        let corners: [Vector3<f32>; 8] = std::array::from_fn(|i| Vector3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        ));
+        self.box_edges(&corners, color);
+    }
+
+    /// Draws a sphere as its three great circles aligned with the world axes.
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, color: [f32; 4]) {
+        const SEGMENTS: usize = 32;
+        let axes = [
+            (Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_x()),
+        ];
+        for (u, v) in axes {
+            let point = |i: usize| {
+                let angle = i as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for i in 0..SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    /// Draws an arrow from `start` to `end` with a four-sided head.
+    pub fn arrow(&mut self, start: Vector3<f32>, end: Vector3<f32>, color: [f32; 4]) {
+        self.line(start, end, color);
+
+        let direction = end - start;
+        let length = direction.magnitude();
+        if length == 0.0 { return; }
+        let direction = direction / length;
+
+        // any vector not parallel to the arrow gives a basis around it
+        let helper = if direction.y.abs() < 0.9 { Vector3::unit_y() } else { Vector3::unit_x() };
+        let side = direction.cross(helper).normalize();
+        let other_side = direction.cross(side);
+
+        let head_length = length * 0.2;
+        let head_base = end - direction * head_length;
+        for offset in [side, -side, other_side, -other_side] {
+            self.line(end, head_base + offset * head_length * 0.4, color);
+        }
+    }
+
+    /// Draws `text` with a stroke font, centered on `position` and always facing the camera.
+    /// `height` is the height of a character in world units. Letters are drawn as uppercase.
+    pub fn text3d(&mut self, text: &str, position: Vector3<f32>, height: f32, color: [f32; 4]) {
+        self.texts.push(DebugText { text: text.to_string(), position, height, color });
+    }
+
+    /// Draws the 12 edges of a box given its corners, ordered as the bits of their index: x = 1, y = 2, z = 4.
+    pub(crate) fn box_edges(&mut self, corners: &[Vector3<f32>; 8], color: [f32; 4]) {
+        for i in 0..8 {
+            for axis_bit in [1, 2, 4] {
+                if i & axis_bit == 0 {
+                    self.line(corners[i], corners[i | axis_bit], color);
+                }
+            }
+        }
+    }
+
+    /// Adds the lines requested by the debug options of `mesh`.
+    pub(crate) fn add_mesh(&mut self, mesh: &Mesh) {
+        let options = mesh.debug;
+        if !options.vertex_normals && !options.face_normals && !options.bounding_box { return; }
+
+        let world = mesh.transform.compute_world_matrix();
+        let normal_matrix = upper_left(&world).invert().unwrap_or_else(Matrix3::identity).transpose();
+        let to_world = |position: &[f32; 3]| transform_point(&world, Vector3::from(*position));
+
+        if options.vertex_normals {
+            for (position, normal) in mesh.positions.iter().zip(mesh.normals.iter()) {
+                let start = to_world(position);
+                let direction = (normal_matrix * Vector3::from(*normal)).normalize();
+                self.line(start, start + direction * self.normal_length, VERTEX_NORMAL_COLOR);
+            }
+        }
+
+        if options.face_normals {
+            for triangle in mesh.indices.chunks_exact(3) {
+                let a = to_world(&mesh.positions[triangle[0] as usize]);
+                let b = to_world(&mesh.positions[triangle[1] as usize]);
+                let c = to_world(&mesh.positions[triangle[2] as usize]);
+                let normal = (b - a).cross(c - a);
+                if normal.magnitude2() == 0.0 { continue; }
+
+                let center = (a + b + c) / 3.0;
+                self.line(center, center + normal.normalize() * self.normal_length, FACE_NORMAL_COLOR);
+            }
+        }
+
+        if options.bounding_box {
+            let (min, max) = mesh.bounding_box();
+            let corners: [Vector3<f32>; 8] = std::array::from_fn(|i| to_world(&[
+                if i & 1 == 0 { min[0] } else { max[0] },
+                if i & 2 == 0 { min[1] } else { max[1] },
+                if i & 4 == 0 { min[2] } else { max[2] },
+            ]));
+            self.box_edges(&corners, BOUNDING_BOX_COLOR);
+        }
+    }
+
+    /// Adds the world axes and grid if they are enabled.
+    pub(crate) fn add_helpers(&mut self) {
+        if self.show_grid && self.grid_divisions > 0 {
+            let half_size = self.grid_size / 2.0;
+            let step = self.grid_size / self.grid_divisions as f32;
+            for i in 0..=self.grid_divisions {
+                let offset = -half_size + i as f32 * step;
+                self.line(Vector3::new(offset, 0.0, -half_size), Vector3::new(offset, 0.0, half_size), GRID_COLOR);
+                self.line(Vector3::new(-half_size, 0.0, offset), Vector3::new(half_size, 0.0, offset), GRID_COLOR);
+            }
+        }
+
+        if self.show_axes {
+            let length = self.grid_size / 2.0;
+            let origin = Vector3::new(0.0, 0.0, 0.0);
+            self.line(origin, Vector3::unit_x() * length, [1.0, 0.0, 0.0, 1.0]);
+            self.line(origin, Vector3::unit_y() * length, [0.0, 1.0, 0.0, 1.0]);
+            self.line(origin, Vector3::unit_z() * length, [0.0, 0.0, 1.0, 1.0]);
+        }
+    }
+
+    /// Turns the texts into lines facing `camera` and uploads the collected vertices, growing the vertex buffer if needed.
+    pub(crate) fn upload(&mut self, camera: &BasicCamera, engine: &Engine) {
+        let view = camera.get_view_matrix();
+        let right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        let up = Vector3::new(view.x.y, view.y.y, view.z.y);
+        for text in std::mem::take(&mut self.texts) {
+            self.add_text_lines(&text, right, up);
+        }
+
+        if self.line_vertices.len() > self.line_buffer_capacity {
+            self.line_buffer_capacity = self.line_vertices.len().next_power_of_two();
+            self.line_buffer = create_vertex_buffer(self.line_buffer_capacity, engine);
+        }
+        if !self.line_vertices.is_empty() {
+            engine.queue.write_buffer(&self.line_buffer, 0, cast_slice(&self.line_vertices));
+        }
+    }
+
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let (Some(pipeline), false) = (&self.line_pipeline, self.line_vertices.is_empty()) {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_vertex_buffer(0, self.line_buffer.slice(..));
+            render_pass.draw(0..self.line_vertices.len() as u32, 0..1);
+        }
+    }
+
+    fn add_text_lines(&mut self, text: &DebugText, right: Vector3<f32>, up: Vector3<f32>) {
+        let glyph_width = text.height * GLYPH_ASPECT_RATIO;
+        let advance = glyph_width * 1.4;
+        let line_count = text.text.lines().count().max(1);
+        let line_advance = text.height * 1.5;
+        let top = text.position + up * (((line_count - 1) as f32 * line_advance + text.height) / 2.0);
+
+        for (line_index, line) in text.text.lines().enumerate() {
+            let line_width = line.chars().count() as f32 * advance;
+            let line_origin = top
+                - right * (line_width / 2.0)
+                - up * (line_index as f32 * line_advance + text.height);
+
+            for (char_index, character) in line.chars().enumerate() {
+                let glyph_origin = line_origin + right * (char_index as f32 * advance);
+                let to_world = |(x, y): (f32, f32)| glyph_origin + right * (x * glyph_width) + up * (y * text.height);
+
+                let segments = glyph_segments(character);
+                for (segment, (start, end)) in SEGMENTS.iter().enumerate() {
+                    if segments & (1 << segment) != 0 {
+                        self.line(to_world(*start), to_world(*end), text.color);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.line_vertices.clear();
+        self.texts.clear();
+    }
+}
+
+fn create_vertex_buffer(capacity: usize, engine: &Engine) -> Buffer {
+    engine.device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Vertex Buffer"),
+        size: (capacity * mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn upper_left(matrix: &Matrix4<f32>) -> Matrix3<f32> {
+    Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate())
+}
+
+fn transform_point(matrix: &Matrix4<f32>, point: Vector3<f32>) -> Vector3<f32> {
+    (matrix * Vector4::new(point.x, point.y, point.z, 1.0)).truncate()
+}
+
+// The debug font is a 16-segment display: each glyph is a set of segments between the points of a 3x3 grid
+// spanning the unit square, x to the right and y upwards.
+const GLYPH_ASPECT_RATIO: f32 = 0.6;
+
+const A1: u16 = 1 << 0;
+const A2: u16 = 1 << 1;
+const B: u16 = 1 << 2;
+const C: u16 = 1 << 3;
+const D2: u16 = 1 << 4;
+const D1: u16 = 1 << 5;
+const E: u16 = 1 << 6;
+const F: u16 = 1 << 7;
+const G1: u16 = 1 << 8;
+const G2: u16 = 1 << 9;
+const H: u16 = 1 << 10;
+const I: u16 = 1 << 11;
+const J: u16 = 1 << 12;
+const K: u16 = 1 << 13;
+const L: u16 = 1 << 14;
+const M: u16 = 1 << 15;
+
+/// Endpoints of each segment, in the order of the bits above.
+const SEGMENTS: [((f32, f32), (f32, f32)); 16] = [
+    ((0.0, 1.0), (0.5, 1.0)), // A1: top left
+    ((0.5, 1.0), (1.0, 1.0)), // A2: top right
+    ((1.0, 1.0), (1.0, 0.5)), // B: right upper
+    ((1.0, 0.5), (1.0, 0.0)), // C: right lower
+    ((1.0, 0.0), (0.5, 0.0)), // D2: bottom right
+    ((0.5, 0.0), (0.0, 0.0)), // D1: bottom left
+    ((0.0, 0.0), (0.0, 0.5)), // E: left lower
+    ((0.0, 0.5), (0.0, 1.0)), // F: left upper
+    ((0.0, 0.5), (0.5, 0.5)), // G1: middle left
+    ((0.5, 0.5), (1.0, 0.5)), // G2: middle right
+    ((0.0, 1.0), (0.5, 0.5)), // H: diagonal top left
+    ((0.5, 1.0), (0.5, 0.5)), // I: vertical top
+    ((1.0, 1.0), (0.5, 0.5)), // J: diagonal top right
+    ((0.5, 0.5), (1.0, 0.0)), // K: diagonal bottom right
+    ((0.5, 0.5), (0.5, 0.0)), // L: vertical bottom
+    ((0.5, 0.5), (0.0, 0.0)), // M: diagonal bottom left
+];
+
+fn glyph_segments(character: char) -> u16 {
+    match character.to_ascii_uppercase() {
+        '0' => A1 | A2 | B | C | D1 | D2 | E | F | J | M,
+        '1' => B | C | J,
+        '2' => A1 | A2 | B | G1 | G2 | E | D1 | D2,
+        '3' => A1 | A2 | B | C | D1 | D2 | G2,
+        '4' => F | G1 | G2 | B | C,
+        '5' => A1 | A2 | F | G1 | G2 | C | D1 | D2,
+        '6' => A1 | A2 | F | E | D1 | D2 | C | G1 | G2,
+        '7' => A1 | A2 | B | C,
+        '8' => A1 | A2 | B | C | D1 | D2 | E | F | G1 | G2,
+        '9' => A1 | A2 | B | C | D1 | D2 | F | G1 | G2,
+        'A' => E | F | A1 | A2 | B | C | G1 | G2,
+        'B' => A1 | A2 | B | C | D1 | D2 | G2 | I | L,
+        'C' => A1 | A2 | F | E | D1 | D2,
+        'D' => A1 | A2 | B | C | D1 | D2 | I | L,
+        'E' => A1 | A2 | F | E | D1 | D2 | G1,
+        'F' => A1 | A2 | F | E | G1,
+        'G' => A1 | A2 | F | E | D1 | D2 | C | G2,
+        'H' => F | E | B | C | G1 | G2,
+        'I' => A1 | A2 | I | L | D1 | D2,
+        'J' => B | C | D1 | D2 | E,
+        'K' => F | E | G1 | J | K,
+        'L' => F | E | D1 | D2,
+        'M' => F | E | B | C | H | J,
+        'N' => F | E | B | C | H | K,
+        'O' => A1 | A2 | B | C | D1 | D2 | E | F,
+        'P' => A1 | A2 | B | F | E | G1 | G2,
+        'Q' => A1 | A2 | B | C | D1 | D2 | E | F | K,
+        'R' => A1 | A2 | B | F | E | G1 | G2 | K,
+        'S' => A1 | A2 | F | G1 | G2 | C | D1 | D2,
+        'T' => A1 | A2 | I | L,
+        'U' => F | E | D1 | D2 | C | B,
+        'V' => F | E | M | J,
+        'W' => F | E | B | C | M | K,
+        'X' => H | J | M | K,
+        'Y' => H | J | L,
+        'Z' => A1 | A2 | J | M | D1 | D2,
+        '-' => G1 | G2,
+        '+' => G1 | G2 | I | L,
+        '=' => G1 | G2 | D1 | D2,
+        '_' => D1 | D2,
+        '*' => G1 | G2 | H | I | J | K | L | M,
+        '/' => J | M,
+        '\\' => H | K,
+        '<' | '(' | '[' => J | K,
+        '>' | ')' | ']' => H | M,
+        '.' | ',' => M,
+        ':' => I | L,
+        '\'' | '"' => I,
+        _ => 0,
+    }
+}
@@ -1,11 +1,17 @@
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Window, WindowBuilder, WindowId};
 use cgmath::*;
-use wgpu::{Device, Queue, Surface, SurfaceConfiguration};
+use wgpu::{Adapter, Device, Instance, Queue, Surface, SurfaceConfiguration};
 use winit::dpi::PhysicalSize;
 use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::platform::unix::EventLoopExtUnix;
+use std::path::{Path, PathBuf};
+use crate::hot_reload::ShaderWatcher;
+use crate::post_process::HDR_FORMAT;
+use crate::render_graph::TexturePool;
+use crate::render_target::{RenderTargets, DEPTH_FORMAT};
 use crate::scene::Scene;
+use crate::uniforms::BindGroupLayouts;
 
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -14,66 +20,261 @@ pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
-pub struct Engine {
+/// A window of the engine, with its surface and the buffers its scene is rendered with. See `Engine::add_window`.
+pub struct EngineWindow {
     pub window: Window,
     pub surface: Surface,
-    pub device: Device,
-    pub queue: Queue,
     pub config: SurfaceConfiguration,
     pub size: PhysicalSize<u32>,
+    /// buffers of the passes of the scene, kept at the size of the window
+    pub render_targets: RenderTargets,
+    /// transient textures of the render graphs of the frames, kept apart from the ones of the other windows
+    pub texture_pool: TexturePool,
+}
+
+impl EngineWindow {
+    fn new(window: Window, surface: Surface, format: wgpu::TextureFormat, device: &Device, layouts: &BindGroupLayouts, sample_count: u32) -> EngineWindow {
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        surface.configure(device, &config);
+        let render_targets = RenderTargets::new(device, layouts, config.width, config.height, sample_count);
+
+        EngineWindow {
+            window,
+            surface,
+            config,
+            size,
+            render_targets,
+            texture_pool: TexturePool::default(),
+        }
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    fn resize(&mut self, new_size: PhysicalSize<u32>, device: &Device, layouts: &BindGroupLayouts, sample_count: u32) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            self.surface.configure(device, &self.config);
+            self.render_targets.resize(device, layouts, new_size.width, new_size.height, sample_count);
+        }
+    }
+}
+
+pub struct Engine {
+    /// the window created with the engine, then the ones of `add_window`
+    pub windows: Vec<EngineWindow>,
+    pub device: Device,
+    pub queue: Queue,
+    /// format of the surfaces of every window, that the pipelines drawing on screen output
+    pub surface_format: wgpu::TextureFormat,
+    pub layouts: BindGroupLayouts,
+    pub shader_watcher: Option<ShaderWatcher>,
+    /// number of polls of the shader watcher that found changed files, each scene reloading its shaders when it
+    /// differs from the last one it saw, see `enable_shader_hot_reload`
+    pub shader_changes: u64,
+    /// shader files changed in the latest of `shader_changes`
+    pub changed_shaders: Vec<PathBuf>,
+    /// number of samples per pixel of the color and depth buffers of the main pass, fixed at creation, see `with_sample_count`
+    sample_count: u32,
+    /// sample counts the adapter supports for the color and depth buffers of the main pass, in increasing order
+    pub supported_sample_counts: Vec<u32>,
+    /// to create the surfaces of new windows
+    instance: Instance,
+    adapter: Adapter,
 }
 
 
 impl Engine {
+    /// Creates the engine with 4x multisample anti-aliasing, or none if the adapter does not support it.
     pub fn new(name: &str, any_thread: bool) -> (Self, EventLoop<()>) {
+        Engine::with_sample_count(name, any_thread, 4)
+    }
+
+    /// Creates the engine with `sample_count` samples per pixel of multisample anti-aliasing, 1 to disable it,
+    /// falling back to the highest supported count below it with a warning. Only 1 and 4 are supported, being the counts
+    /// WebGPU guarantees, as wgpu 0.13 cannot tell whether the adapter supports the others.
+    /// Every pipeline of the main pass is built for this count, so it cannot change afterwards.
+    pub fn with_sample_count(name: &str, any_thread: bool, sample_count: u32) -> (Self, EventLoop<()>) {
         env_logger::init();
         let event_loop = if any_thread { EventLoop::new_any_thread() } else { EventLoop::new() };
         let window = WindowBuilder::new().build(&event_loop).unwrap();
         window.set_title(name);
 
-        let (surface, device, queue, config, size) = pollster::block_on(init_wgpu(&window));
+        let (instance, adapter, surface, device, queue, supported_sample_counts) = pollster::block_on(init_wgpu(&window));
+        let surface_format = *surface.get_supported_formats(&adapter).first().unwrap();
+        let layouts = BindGroupLayouts::new(&device);
+        let requested_sample_count = sample_count;
+        let sample_count = supported_sample_counts.iter().copied()
+            .filter(|&count| count <= requested_sample_count)
+            .max()
+            .unwrap_or(1);
+        if sample_count != requested_sample_count {
+            eprintln!("{} samples per pixel are not supported, using {} instead (supported: {:?})", requested_sample_count, sample_count, supported_sample_counts);
+        }
+        let main_window = EngineWindow::new(window, surface, surface_format, &device, &layouts, sample_count);
 
         let app = Engine {
-            window,
-            surface,
+            windows: vec![main_window],
             device,
             queue,
-            config,
-            size,
+            surface_format,
+            layouts,
+            shader_watcher: None,
+            shader_changes: 0,
+            changed_shaders: Vec::new(),
+            sample_count,
+            supported_sample_counts,
+            instance,
+            adapter,
         };
 
         (app, event_loop)
     }
 
+    /// Opens another window drawn by the same device, to be given its own scene, see `Scene::for_window` and `start_with_scenes`.
+    /// Panics if its surface does not support the format of the surface of the first window.
+    pub fn add_window(&mut self, title: &str, event_loop: &EventLoop<()>) -> WindowId {
+        let window = WindowBuilder::new().with_title(title).build(event_loop).unwrap();
+        let surface = unsafe { self.instance.create_surface(&window) };
+        assert!(surface.get_supported_formats(&self.adapter).contains(&self.surface_format),
+                "The surface of the window \"{}\" does not support the format {:?} of the first window", title, self.surface_format);
+
+        let window = EngineWindow::new(window, surface, self.surface_format, &self.device, &self.layouts, self.sample_count);
+        let id = window.id();
+        self.windows.push(window);
+        id
+    }
+
+    /// The window created with the engine.
+    pub fn main_window(&self) -> &EngineWindow {
+        &self.windows[0]
+    }
+
+    /// Panics if no window of the engine has this id, such as a closed window.
+    pub fn window_by_id(&self, id: WindowId) -> &EngineWindow {
+        self.windows.iter().find(|window| window.id() == id).expect("No window of the engine has this id")
+    }
+
+    /// Panics if no window of the engine has this id, such as a closed window.
+    pub fn window_by_id_mut(&mut self, id: WindowId) -> &mut EngineWindow {
+        self.windows.iter_mut().find(|window| window.id() == id).expect("No window of the engine has this id")
+    }
+
+    /// The main window, see `main_window`.
+    pub fn window(&self) -> &Window {
+        &self.main_window().window
+    }
+
+    pub fn surface(&self) -> &Surface {
+        &self.main_window().surface
+    }
+
+    pub fn config(&self) -> &SurfaceConfiguration {
+        &self.main_window().config
+    }
+
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.main_window().size
+    }
+
+    /// Buffers of the passes of the scene of the main window.
+    pub fn render_targets(&self) -> &RenderTargets {
+        &self.main_window().render_targets
+    }
+
+    /// Transient textures of the render graphs of the main window.
+    pub fn texture_pool(&self) -> &TexturePool {
+        &self.main_window().texture_pool
+    }
+
+    /// Development mode: materials created afterwards load their WGSL from `shader_dir` instead of the binary,
+    /// and are recompiled on the fly by the scene whenever a file of that directory changes.
+    pub fn enable_shader_hot_reload(&mut self, shader_dir: impl Into<PathBuf>) {
+        self.shader_watcher = Some(ShaderWatcher::new(shader_dir));
+    }
+
+    /// Number of samples per pixel of the multisample anti-aliasing, 1 when it is disabled.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn shader_dir(&self) -> Option<&Path> {
+        self.shader_watcher.as_ref().map(|watcher| watcher.shader_dir.as_path())
+    }
+
+    /// Resizes the main window.
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+        let window_id = self.main_window().id();
+        self.resize_window(window_id, new_size);
+    }
+
+    pub fn resize_window(&mut self, window_id: WindowId, new_size: PhysicalSize<u32>) {
+        let (device, layouts, sample_count) = (&self.device, &self.layouts, self.sample_count);
+        if let Some(window) = self.windows.iter_mut().find(|window| window.id() == window_id) {
+            window.resize(new_size, device, layouts, sample_count);
         }
     }
 
+    /// Handles an event of the main window.
     pub fn manage_event(&mut self, event: &WindowEvent) {
+        let window_id = self.main_window().id();
+        self.manage_window_event(window_id, event);
+    }
+
+    pub fn manage_window_event(&mut self, window_id: WindowId, event: &WindowEvent) {
         match event {
             WindowEvent::Resized(physical_size) => {
-                self.resize(*physical_size);
+                self.resize_window(window_id, *physical_size);
             }
             WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                self.resize(**new_inner_size);
+                self.resize_window(window_id, **new_inner_size);
             }
             _ => {}
         }
     }
 
-    pub fn start(mut self, mut scene: Scene, event_loop: EventLoop<()>, mut callback: impl FnMut() + 'static) {
+    /// Counts a new change in `shader_changes` if shader files changed since the last poll, replacing `changed_shaders`
+    /// with them, for the scenes to reload their shaders.
+    /// Called by `Scene::update`, the watcher being polled at most once per `ShaderWatcher::interval`.
+    pub fn poll_shader_changes(&mut self) {
+        if let Some(watcher) = self.shader_watcher.as_mut() {
+            let changed = watcher.poll();
+            if !changed.is_empty() {
+                self.shader_changes += 1;
+                self.changed_shaders = changed;
+            }
+        }
+    }
+
+    /// Runs the event loop. `callback` is called every frame after the scene update and before rendering,
+    /// it can for instance submit debug geometry with `Scene::debug`.
+    pub fn start(self, scene: Scene, event_loop: EventLoop<()>, callback: impl FnMut(&mut Scene) + 'static) {
+        self.start_with_scenes(vec![scene], event_loop, callback);
+    }
+
+    /// Runs the event loop with one scene per window, each getting the events of its own window, see `Scene::for_window`.
+    /// `callback` is called for every scene each frame, after its update and before rendering.
+    /// Closing the first window exits, closing another one drops it and its scene.
+    pub fn start_with_scenes(mut self, mut scenes: Vec<Scene>, event_loop: EventLoop<()>, mut callback: impl FnMut(&mut Scene) + 'static) {
         event_loop.run(move |event, _, control_flow| match event {
             Event::WindowEvent {
                 ref event,
                 window_id
-            } if window_id == self.window.id() => {
-                self.manage_event(event);
-                scene.manage_event(event);
+            } if self.windows.iter().any(|window| window.id() == window_id) => {
+                self.manage_window_event(window_id, event);
+                for scene in scenes.iter_mut().filter(|scene| scene.window_id() == window_id) {
+                    scene.manage_event(event);
+                }
 
                 match event {
                     WindowEvent::CloseRequested | WindowEvent::KeyboardInput {
@@ -84,35 +285,47 @@ impl Engine {
                             ..
                         },
                         ..
-                    } => *control_flow = ControlFlow::Exit,
+                    } => {
+                        if window_id == self.main_window().id() {
+                            *control_flow = ControlFlow::Exit;
+                        } else {
+                            scenes.retain(|scene| scene.window_id() != window_id);
+                            self.windows.retain(|window| window.id() != window_id);
+                        }
+                    }
                     _ => {}
                 }
             }
-            Event::RedrawRequested(_) => {
-                scene.update(&mut self);
+            Event::RedrawRequested(window_id) => {
+                for scene in scenes.iter_mut().filter(|scene| scene.window_id() == window_id) {
+                    scene.update(&mut self);
 
-                callback();
+                    callback(scene);
 
-                match scene.render(&mut self) {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost) => {
-                        scene.resize(self.window.inner_size());
-                        self.resize(self.window.inner_size());
+                    match scene.render(&mut self) {
+                        Ok(_) => {}
+                        Err(wgpu::SurfaceError::Lost) => {
+                            let size = self.window_by_id(window_id).window.inner_size();
+                            scene.resize(size);
+                            self.resize_window(window_id, size);
+                        }
+                        Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                        Err(e) => eprintln!("{}", e)
                     }
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    Err(e) => eprintln!("{}", e)
                 }
             }
             Event::MainEventsCleared => {
-                self.window.request_redraw();
+                for window in self.windows.iter() {
+                    window.window.request_redraw();
+                }
             }
             _ => {}
         });
     }
 }
 
-pub async fn init_wgpu(window: &Window) -> (Surface, Device, Queue, SurfaceConfiguration, PhysicalSize<u32>) {
-    let size = window.inner_size();
+/// Creates the device with a surface for `window`, returning the sample counts supported by the main pass.
+pub async fn init_wgpu(window: &Window) -> (Instance, Adapter, Surface, Device, Queue, Vec<u32>) {
     let instance = wgpu::Instance::new(wgpu::Backends::all());
     //let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
     let surface = unsafe { instance.create_surface(window) };
@@ -137,14 +350,22 @@ pub async fn init_wgpu(window: &Window) -> (Surface, Device, Queue, SurfaceConfi
         .await
         .unwrap();
 
-    let config = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: *surface.get_supported_formats(&adapter).first().unwrap(),
-        width: size.width,
-        height: size.height,
-        present_mode: wgpu::PresentMode::Fifo,
-    };
-    surface.configure(&device, &config);
-
-    (surface, device, queue, config, size)
-}
\ No newline at end of file
+    // the HDR color is resolved into a single sampled texture at the end of the main pass
+    let hdr_counts = format_sample_counts(&adapter, HDR_FORMAT, wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+    let depth_counts = format_sample_counts(&adapter, DEPTH_FORMAT, wgpu::TextureFormatFeatureFlags::empty());
+    let supported_sample_counts = hdr_counts.into_iter().filter(|count| depth_counts.contains(count)).collect();
+
+    (instance, adapter, surface, device, queue, supported_sample_counts)
+}
+
+/// Sample counts of the textures of `format` according to its format features, which must also contain `required`.
+/// wgpu 0.13 only tells whether a format can be multisampled at all, and does not validate the count of multisampled
+/// textures, so only 4, the count guaranteed by WebGPU for multisampled formats, is advertised.
+fn format_sample_counts(adapter: &Adapter, format: wgpu::TextureFormat, required: wgpu::TextureFormatFeatureFlags) -> Vec<u32> {
+    let flags = adapter.get_texture_format_features(format).flags;
+    if flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE | required) {
+        vec![1, 4]
+    } else {
+        vec![1]
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::Path;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, PipelineLayout, RenderPass, RenderPipeline, TextureView};
+use crate::engine::Engine;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::uniforms::depth_pass_bind_group_layout;
+
+/// How the opacity of the fog grows with the distance to the camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FogMode {
+    /// no fog before `start`, opaque after `end`
+    Linear { start: f32, end: f32 },
+    /// uniform fog starting at `start`, `density` being the fraction of light absorbed per world unit
+    Exponential { start: f32, density: f32 },
+    /// fog of `density` at `base_height`, thinning exponentially along the y axis by `falloff` per world unit,
+    /// filling the valleys of flat terrains
+    Height { density: f32, falloff: f32, base_height: f32 },
+}
+
+/// Fog drawn over the meshes of the scene from the depth buffer, the background being left untouched.
+#[derive(Debug, Copy, Clone)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// linear color of the fog, None to use the average color of the background so that distant meshes fade into it
+    pub color: Option<[f32; 3]>,
+    /// the opacity of the fog never goes over it, to keep distant mountains visible
+    pub max_opacity: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            mode: FogMode::Exponential { start: 0.0, density: 0.02 },
+            color: None,
+            max_opacity: 1.0,
+        }
+    }
+}
+
+/// Uniforms of `fog.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FogUniforms {
+    color: [f32; 3],
+    mode: u32,
+    start: f32,
+    end: f32,
+    density: f32,
+    height_falloff: f32,
+    base_height: f32,
+    max_opacity: f32,
+    _padding: [f32; 2],
+}
+
+impl FogUniforms {
+    fn new(fog: &Fog, background_color: [f32; 3]) -> FogUniforms {
+        let mut uniforms = FogUniforms {
+            color: fog.color.unwrap_or(background_color),
+            max_opacity: fog.max_opacity,
+            ..Zeroable::zeroed()
+        };
+        match fog.mode {
+            FogMode::Linear { start, end } => {
+                uniforms.mode = 0;
+                uniforms.start = start;
+                uniforms.end = end;
+            }
+            FogMode::Exponential { start, density } => {
+                uniforms.mode = 1;
+                uniforms.start = start;
+                uniforms.density = density;
+            }
+            FogMode::Height { density, falloff, base_height } => {
+                uniforms.mode = 2;
+                uniforms.density = density;
+                uniforms.height_falloff = falloff;
+                uniforms.base_height = base_height;
+            }
+        }
+        uniforms
+    }
+}
+
+/// Fullscreen pass blending the fog over the meshes, reading the depth buffer of the main pass.
+pub struct FogRenderer {
+    uniform_buffer: Buffer,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Option<RenderPipeline>,
+}
+
+impl FogRenderer {
+    pub fn new(engine: &Engine) -> FogRenderer {
+        let uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Uniform Buffer"),
+            contents: cast_slice(&[FogUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = depth_pass_bind_group_layout(&engine.device, "Fog Bind Group Layout");
+
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fog Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut renderer = FogRenderer {
+            uniform_buffer,
+            bind_group_layout,
+            pipeline_layout,
+            pipeline: None,
+        };
+        if let Err(e) = renderer.recompile(engine) {
+            eprintln!("Could not create shader \"fog\", the fog will not be drawn: {}", e);
+        }
+        renderer
+    }
+
+    /// Rebuilds the pipeline, keeping the previous one on errors.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        let settings = PipelineSettings {
+            depth_format: None,
+            multisampled: false,
+            blend: wgpu::BlendState::ALPHA_BLENDING,
+            ..Default::default()
+        };
+        let (_, pipeline) = create_pipeline("fog", &preprocessor, &self.pipeline_layout, &[], &settings, engine)?;
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    pub(crate) fn update(&self, fog: &Fog, background_color: [f32; 3], engine: &Engine) {
+        engine.queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[FogUniforms::new(fog, background_color)]));
+    }
+
+    /// Bind group reading `depth_view`, the depth buffer of the frame.
+    pub(crate) fn create_bind_group(&self, depth_view: &TextureView, engine: &Engine) -> BindGroup {
+        engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+            ],
+            label: Some("Fog Bind Group"),
+        })
+    }
+
+    /// Draws the fog, group 0 being bound to the scene bind group.
+    pub(crate) fn draw<'a>(&'a self, bind_group: &'a BindGroup, render_pass: &mut RenderPass<'a>) {
+        if let Some(pipeline) = &self.pipeline {
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
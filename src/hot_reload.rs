@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Watches a directory of WGSL files by polling their modification times.
+/// Polling is throttled to `interval` so it can be called every frame.
+pub struct ShaderWatcher {
+    pub shader_dir: PathBuf,
+    pub interval: Duration,
+    last_poll: Instant,
+    modification_times: HashMap<PathBuf, SystemTime>,
+}
+
+impl ShaderWatcher {
+    pub fn new(shader_dir: impl Into<PathBuf>) -> ShaderWatcher {
+        let shader_dir = shader_dir.into();
+        let mut modification_times = HashMap::new();
+        collect_modification_times(&shader_dir, &mut modification_times);
+
+        ShaderWatcher {
+            shader_dir,
+            interval: Duration::from_millis(500),
+            last_poll: Instant::now(),
+            modification_times,
+        }
+    }
+
+    /// Returns the shader files that were created, modified or deleted since the last call.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut modification_times = HashMap::new();
+        collect_modification_times(&self.shader_dir, &mut modification_times);
+
+        let mut changed: Vec<PathBuf> = modification_times.iter()
+            .filter(|(path, time)| self.modification_times.get(*path) != Some(*time))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(self.modification_times.keys()
+            .filter(|path| !modification_times.contains_key(*path))
+            .cloned());
+
+        self.modification_times = modification_times;
+
+        changed
+    }
+}
+
+fn collect_modification_times(dir: &Path, modification_times: &mut HashMap<PathBuf, SystemTime>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_modification_times(&path, modification_times);
+        } else if path.extension().is_some_and(|extension| extension == "wgsl") {
+            if let Ok(modified) = entry.metadata().and_then(|metadata| metadata.modified()) {
+                modification_times.insert(path, modified);
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::rc::Rc;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use wgpu::{Buffer, RenderPass, RenderPipeline};
+use crate::engine::Engine;
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::shader::ShaderError;
+use crate::transform::Transform;
+use crate::vertex::{VertexAttribute, INSTANCE_LOCATION};
+
+/// A copy of an instanced mesh.
+#[derive(Debug)]
+pub struct Instance {
+    /// placement of the copy relative to the transform of the mesh
+    pub transform: Transform,
+    /// multiplies the vertex colors of the mesh
+    pub color: [f32; 3],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Instance {
+            transform: Transform::new(),
+            color: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl Instance {
+    pub fn new(transform: Transform) -> Instance {
+        Instance {
+            transform,
+            ..Default::default()
+        }
+    }
+}
+
+/// Data of an instance in the instance buffer, see `instance_buffer_layout`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct InstanceData {
+    pub world: [[f32; 4]; 4],
+    pub color: [f32; 4],
+}
+
+impl From<&Instance> for InstanceData {
+    fn from(instance: &Instance) -> Self {
+        let [r, g, b] = instance.color;
+        InstanceData {
+            world: instance.transform.compute_world_matrix().into(),
+            color: [r, g, b, 1.0],
+        }
+    }
+}
+
+/// A mesh drawn many times in a single draw call, such as the rocks or trees scattered on a terrain.
+/// All the copies share the geometry, the material and the transform of `mesh`, each instance being placed relative to it.
+/// Instanced meshes are always drawn with their material, whatever the render mode, and cannot use materials reading
+/// the scene depth, such as water.
+pub struct InstancedMesh {
+    pub mesh: Mesh,
+    instances: Vec<Instance>,
+    instance_buffer: Buffer,
+    /// number of instances the buffer can hold
+    instance_capacity: usize,
+    /// variant of the pipeline of the material of the mesh reading the instance buffer
+    pipeline: RenderPipeline,
+}
+
+impl InstancedMesh {
+    /// Panics if the material of `mesh` cannot be instanced, see `set_material`.
+    pub fn new(mesh: Mesh, instances: Vec<Instance>, engine: &Engine) -> InstancedMesh {
+        let pipeline = create_instanced_pipeline(mesh.material(), engine);
+        let data: Vec<InstanceData> = instances.iter().map(InstanceData::from).collect();
+        let instance_buffer = create_instance_buffer(&data, engine);
+
+        InstancedMesh {
+            mesh,
+            instance_capacity: instances.len(),
+            instances,
+            instance_buffer,
+            pipeline,
+        }
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// Replaces the instances and uploads them, growing the instance buffer when needed.
+    pub fn set_instances(&mut self, instances: Vec<Instance>, engine: &Engine) {
+        let data: Vec<InstanceData> = instances.iter().map(InstanceData::from).collect();
+        if instances.len() > self.instance_capacity {
+            self.instance_buffer = create_instance_buffer(&data, engine);
+            self.instance_capacity = instances.len();
+        } else if !data.is_empty() {
+            engine.queue.write_buffer(&self.instance_buffer, 0, cast_slice(&data));
+        }
+        self.instances = instances;
+    }
+
+    /// Replaces the material of the mesh, compiling its instanced variant.
+    /// Panics if the mesh lacks a vertex attribute the material reads, if the material reads custom attributes
+    /// at the locations of the instance data or if it reads the scene depth.
+    pub fn set_material(&mut self, material: Rc<Material>, engine: &Engine) {
+        let pipeline = create_instanced_pipeline(&material, engine);
+        self.mesh.set_material(material).unwrap_or_else(|error| panic!("{}", error));
+        self.pipeline = pipeline;
+    }
+
+    /// Rebuilds the instanced pipeline from the shader of the material, keeping the previous one on errors.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        self.pipeline = self.mesh.material().create_instanced_pipeline(engine)?;
+        Ok(())
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        let material = self.mesh.material();
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, &material.uniform_bind_group, &[]);
+        self.draw_geometry(&material.vertex_layouts.attributes, render_pass);
+    }
+
+    /// Draws the instances with a pipeline other than the instanced pipeline of the material, such as the shadow pipeline.
+    /// The pipeline is expected to read `attributes` followed by the instance buffer.
+    pub fn draw_with_pipeline<'a>(&'a self, pipeline: &'a RenderPipeline, attributes: &[VertexAttribute], render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(pipeline);
+        self.draw_geometry(attributes, render_pass);
+    }
+
+    fn draw_geometry<'a>(&'a self, attributes: &[VertexAttribute], render_pass: &mut RenderPass<'a>) {
+        if self.instances.is_empty() { return; }
+        render_pass.set_vertex_buffer(attributes.len() as u32, self.instance_buffer.slice(..));
+        self.mesh.draw_instances(attributes, 0..self.instances.len() as u32, render_pass);
+    }
+}
+
+fn create_instanced_pipeline(material: &Material, engine: &Engine) -> RenderPipeline {
+    if material.reads_scene_depth {
+        panic!("The material \"{}\" reads the scene depth and cannot be instanced", material.shader_name);
+    }
+    if let Some(attribute) = material.vertex_layouts.attributes.iter().find(|attribute| attribute.location() >= INSTANCE_LOCATION) {
+        panic!("The material \"{}\" reads the vertex attribute {:?} at the location of the instance data", material.shader_name, attribute);
+    }
+    material.create_instanced_pipeline(engine)
+        .unwrap_or_else(|e| panic!("Could not create shader \"{}\" for instancing: {}", material.shader_name, e))
+}
+
+fn create_instance_buffer(data: &[InstanceData], engine: &Engine) -> Buffer {
+    // empty buffers cannot be bound, keep room for one instance
+    let contents = if data.is_empty() { cast_slice(&[InstanceData::zeroed()]).to_vec() } else { cast_slice(data).to_vec() };
+    engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Instance Buffer"),
+        contents: &contents,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
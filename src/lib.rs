@@ -1,7 +1,27 @@
 pub mod scene;
 pub mod camera;
 pub mod mesh;
+pub mod vertex;
 pub mod procedural;
 pub mod transform;
 pub mod material;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod shader;
+pub mod hot_reload;
+pub mod uniforms;
+pub mod render_mode;
+pub mod debug;
+pub mod texture;
+pub mod light;
+pub mod shadow;
+pub mod atmosphere;
+pub mod fog;
+pub mod post_process;
+pub mod msaa;
+pub mod render_target;
+pub mod background;
+pub mod instancing;
+pub mod scatter;
+pub mod render_texture;
+pub mod render_graph;
+pub mod viewport;
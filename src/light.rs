@@ -0,0 +1,70 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+
+/// A light infinitely far away, such as the sun.
+#[derive(Debug, Copy, Clone)]
+pub struct DirectionalLight {
+    /// direction from the scene towards the light
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            direction: Vector3::new(1.0, 1.0, 0.5),
+            color: [1.0, 0.96, 0.9],
+            intensity: 3.0,
+        }
+    }
+}
+
+/// Ambient light fading from `ground_color` for surfaces facing down to `sky_color` for surfaces facing up.
+#[derive(Debug, Copy, Clone)]
+pub struct HemisphereLight {
+    pub sky_color: [f32; 3],
+    pub ground_color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for HemisphereLight {
+    fn default() -> Self {
+        HemisphereLight {
+            sky_color: [0.55, 0.65, 0.8],
+            ground_color: [0.2, 0.17, 0.15],
+            intensity: 0.3,
+        }
+    }
+}
+
+/// Uniforms of the scene lights, bound at group 0, binding 1.
+/// The environment map used for image based lighting is bound at bindings 2 and 3.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct LightUniforms {
+    pub sun_direction: [f32; 3],
+    pub sun_intensity: f32,
+    pub sun_color: [f32; 3],
+    pub ambient_intensity: f32,
+    pub sky_color: [f32; 3],
+    /// 0 without environment map
+    pub environment_intensity: f32,
+    pub ground_color: [f32; 3],
+    pub environment_mip_count: f32,
+}
+
+impl LightUniforms {
+    pub fn new(sun: &DirectionalLight, ambient: &HemisphereLight, environment_intensity: f32, environment_mip_count: u32) -> LightUniforms {
+        LightUniforms {
+            sun_direction: sun.direction.normalize().into(),
+            sun_intensity: sun.intensity,
+            sun_color: sun.color,
+            ambient_intensity: ambient.intensity,
+            sky_color: ambient.sky_color,
+            environment_intensity,
+            ground_color: ambient.ground_color,
+            environment_mip_count: environment_mip_count as f32,
+        }
+    }
+}
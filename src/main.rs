@@ -1,21 +1,30 @@
 extern crate gilgamesh;
 
+use cgmath::Zero;
+use gilgamesh::atmosphere::Atmosphere;
 use gilgamesh::engine::Engine;
+use gilgamesh::material::WaterOptions;
 use gilgamesh::mesh::Mesh;
 use gilgamesh::scene::Scene;
+use gilgamesh::shader::SHADER_SOURCE_DIR;
 
 fn main() {
     let (mut engine, event_loop) = Engine::new("Gilgamesh", false);
+    if cfg!(debug_assertions) {
+        engine.enable_shader_hot_reload(SHADER_SOURCE_DIR);
+    }
 
     let mut scene = Scene::new(&engine);
 
     let sphere = Mesh::new_procedural_sphere(5.0, 32, &|x, y, z| {
         f32::powi(f32::sin(60.0 * x * y * z), 2) * 0.5
-    }, 0.5, &mut engine);
+    }, 0.5, &engine);
 
     scene.add_mesh(sphere);
+    scene.add_mesh(Mesh::new_ocean_sphere(2.7, 16, &WaterOptions::default(), &engine));
+    scene.atmosphere = Some(Atmosphere::for_planet(cgmath::Vector3::zero(), 2.5));
 
-    engine.start(scene, event_loop, move || {
-        println!("Hello, world!");
+    engine.start(scene, event_loop, move |scene| {
+        scene.debug().text3d("Hello, world!", cgmath::Vector3::new(0.0, 4.0, 0.0), 0.3, [1.0, 1.0, 1.0, 1.0]);
     });
 }
\ No newline at end of file
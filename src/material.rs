@@ -1,345 +1,659 @@
-use bytemuck::cast_slice;
-use cgmath::{Matrix4, SquareMatrix};
+use std::path::Path;
+use std::rc::Rc;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
 use wgpu::{BindGroup, BindGroupLayout, Buffer, PipelineLayout, RenderPass, RenderPipeline, ShaderModule};
-use wgpu::util::{DeviceExt};
 use crate::engine::Engine;
+use crate::post_process::HDR_FORMAT;
+use crate::render_target::DEPTH_FORMAT;
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::texture::Texture;
+use crate::vertex::{instance_buffer_layout, VertexAttribute, VertexBufferLayouts, BASE_ATTRIBUTES};
 
-use crate::mesh::Vertex;
+/// Fixed-function state of a render pipeline that is not dictated by the shader.
+#[derive(Debug, Clone)]
+pub struct PipelineSettings {
+    pub topology: wgpu::PrimitiveTopology,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub depth_write_enabled: bool,
+    pub depth_bias: wgpu::DepthBiasState,
+    /// None for passes without depth buffer, such as fullscreen passes
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub blend: wgpu::BlendState,
+    /// the HDR color buffer of the scene by default, the surface for passes drawn after post-processing,
+    /// None for fragment shaders only writing the depth
+    pub color_format: Option<wgpu::TextureFormat>,
+    /// pipelines without fragment stage nor color target, such as the shadow map pass
+    pub depth_only: bool,
+    /// drawn in the main pass, with `Engine::sample_count` samples per pixel, rather than in a single sampled pass
+    pub multisampled: bool,
+}
+
+impl Default for PipelineSettings {
+    fn default() -> Self {
+        PipelineSettings {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth_write_enabled: true,
+            depth_bias: wgpu::DepthBiasState::default(),
+            depth_format: Some(DEPTH_FORMAT),
+            blend: wgpu::BlendState::REPLACE,
+            color_format: Some(HDR_FORMAT),
+            depth_only: false,
+            multisampled: true,
+        }
+    }
+}
+
+/// Detail textures of the terrain materials, replacing the flat grass, slope and snow colors.
+/// `scale` is the number of texture repetitions per world unit.
+#[derive(Clone)]
+pub struct TerrainTextures {
+    pub grass: Rc<Texture>,
+    pub rock: Rc<Texture>,
+    pub snow: Rc<Texture>,
+    pub scale: f32,
+}
+
+/// How the detail textures of the terrain materials are mapped on the terrain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureProjection {
+    /// projected on the xz plane, cheap but stretched on steep slopes and unusable on spheres
+    Planar,
+    /// projected on the three axis planes and blended by the normal
+    Triplanar,
+}
+
+/// Space of the normals stored in a normal map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NormalMapSpace {
+    /// relative to the surface, for tiling detail normals, the mesh needs uvs and tangents
+    Tangent,
+    /// in the object space of the mesh, covering the whole mesh through its uvs,
+    /// such as the maps baked by `Texture::new_terrain_normal_map`
+    Object,
+}
+
+/// A normal map perturbing the mesh normals in the lighting of a material.
+/// The texture should be created with `TextureOptions::linear()` since it holds vectors, not colors.
+#[derive(Clone)]
+pub struct NormalMap {
+    pub texture: Rc<Texture>,
+    pub space: NormalMapSpace,
+    /// 0 keeps the mesh normals, 1 uses the normal map as is
+    pub strength: f32,
+    /// number of repetitions of the texture over the uv range of the mesh
+    pub scale: f32,
+}
+
+/// Physical properties of a surface for the metallic-roughness lighting model.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceProperties {
+    /// 0 for a mirror, 1 for a fully diffuse surface
+    pub roughness: f32,
+    /// 0 for dielectrics such as rock or plastic, 1 for metals
+    pub metallic: f32,
+}
+
+/// Parameters of the terrain materials. `new_2d_terrain` and the other terrain constructors are shortcuts for the common cases.
+#[derive(Clone)]
+pub struct TerrainOptions {
+    pub max_height: f32,
+    /// radius of terrains made with `new_procedural_sphere`, None for flat terrains
+    pub sphere_radius: Option<f32>,
+    pub textures: Option<TerrainTextures>,
+    pub projection: TextureProjection,
+    pub normal_map: Option<NormalMap>,
+    /// surface properties of the grass, rock and snow layers, blended like their colors
+    pub grass_surface: SurfaceProperties,
+    pub rock_surface: SurfaceProperties,
+    pub snow_surface: SurfaceProperties,
+}
+
+impl Default for TerrainOptions {
+    fn default() -> Self {
+        TerrainOptions {
+            max_height: 1.0,
+            sphere_radius: None,
+            textures: None,
+            projection: TextureProjection::Planar,
+            normal_map: None,
+            grass_surface: SurfaceProperties { roughness: 0.9, metallic: 0.0 },
+            rock_surface: SurfaceProperties { roughness: 0.75, metallic: 0.0 },
+            snow_surface: SurfaceProperties { roughness: 0.35, metallic: 0.0 },
+        }
+    }
+}
+
+/// Parameters of the standard physically based material, see `Material::new_pbr`.
+#[derive(Clone)]
+pub struct PbrOptions {
+    /// linear color, multiplied by `base_color_texture` if any
+    pub base_color: [f32; 3],
+    /// sampled with the uvs of the mesh
+    pub base_color_texture: Option<Rc<Texture>>,
+    pub surface: SurfaceProperties,
+    pub normal_map: Option<NormalMap>,
+}
+
+impl Default for PbrOptions {
+    fn default() -> Self {
+        PbrOptions {
+            base_color: [0.8, 0.8, 0.8],
+            base_color_texture: None,
+            surface: SurfaceProperties { roughness: 0.5, metallic: 0.0 },
+            normal_map: None,
+        }
+    }
+}
+
+/// Parameters of the water material, see `Material::new_water`.
+/// Colors are linear and lengths in world units.
+#[derive(Debug, Copy, Clone)]
+pub struct WaterOptions {
+    /// color of the water over the shore
+    pub shallow_color: [f32; 3],
+    /// color of the water where the bottom cannot be seen
+    pub deep_color: [f32; 3],
+    /// fraction of the light absorbed per world unit of water between the surface and the bottom
+    pub absorption: f32,
+    /// roughness of the reflection of the sun
+    pub roughness: f32,
+    /// tilt of the normals by the waves, 0 for a flat mirror
+    pub wave_strength: f32,
+    /// length of the longest waves
+    pub wavelength: f32,
+    /// speed of the waves in world units per second
+    pub wave_speed: f32,
+    pub foam_color: [f32; 3],
+    /// thickness of water under which the shore is covered with foam, 0 for none
+    pub foam_distance: f32,
+}
+
+impl Default for WaterOptions {
+    fn default() -> Self {
+        WaterOptions {
+            shallow_color: [0.1, 0.45, 0.45],
+            deep_color: [0.01, 0.05, 0.1],
+            absorption: 2.0,
+            roughness: 0.08,
+            wave_strength: 0.15,
+            wavelength: 1.0,
+            wave_speed: 0.3,
+            foam_color: [0.9, 0.95, 1.0],
+            foam_distance: 0.05,
+        }
+    }
+}
 
+/// Fragment uniforms of `terrain.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct TerrainUniforms {
+    roughness: [f32; 3],
+    max_height: f32,
+    metallic: [f32; 3],
+    sphere_radius: f32,
+    texture_scale: f32,
+    triplanar_sharpness: f32,
+    normal_map_strength: f32,
+    normal_map_scale: f32,
+}
+
+/// Fragment uniforms of `standard.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct StandardUniforms {
+    base_color: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    normal_map_strength: f32,
+    normal_map_scale: f32,
+}
+
+/// Fragment uniforms of `water.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct WaterUniforms {
+    shallow_color: [f32; 3],
+    absorption: f32,
+    deep_color: [f32; 3],
+    roughness: f32,
+    foam_color: [f32; 3],
+    foam_distance: f32,
+    wave_strength: f32,
+    wavelength: f32,
+    wave_speed: f32,
+    _padding: f32,
+}
+
+/// A shader with its pipeline and its own uniforms, bound at group 2.
+/// Groups 0 (camera and lights) and 1 (mesh transform) are shared by every material, see `uniforms.rs`.
+/// Reading `Uv` or `Tangent` defines `HAS_UV` or `HAS_TANGENT` in the shader, enabling them in the `vertex` chunk.
 pub struct Material {
+    pub shader_name: String,
+    pub preprocessor: ShaderPreprocessor,
+    pub settings: PipelineSettings,
+    /// vertex attributes read by the shader, a mesh must provide all of them to use the material
+    pub vertex_layouts: VertexBufferLayouts,
     pub shader_module: ShaderModule,
+    /// identity matrix the shaders used to read as their model-view-projection matrix, no longer bound
+    #[deprecated(note = "the matrices are in the camera and mesh uniforms at groups 0 and 1, see `CameraUniforms` and `MeshUniforms`")]
     pub vertex_uniform_buffer: Buffer,
+    pub fragment_uniform_buffer: Option<Buffer>,
+    pub textures: Vec<Rc<Texture>>,
     pub uniform_bind_group_layout: BindGroupLayout,
     pub uniform_bind_group: BindGroup,
     pub pipeline_layout: PipelineLayout,
     pub pipeline: RenderPipeline,
+    /// set for shaders defining `READS_SCENE_DEPTH`, such as water. The mesh is drawn after the other meshes,
+    /// blended over them without depth buffer and reading their depth at group 3 through the `depth` chunk
+    pub reads_scene_depth: bool,
 }
 
 impl Material {
-    pub fn new_default(engine: &mut Engine) -> Material {
-        let shader = engine.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-        });
+    pub fn new_default(engine: &Engine) -> Material {
+        Material::from_shader("default", &ShaderPreprocessor::new(), &BASE_ATTRIBUTES, None, Vec::new(), engine)
+    }
 
-        let mvp: Matrix4<f32> = Matrix4::identity();
-        let mvp_ref: &[f32; 16] = mvp.as_ref();
-        let vertex_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: cast_slice(mvp_ref),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    /// Variant of the default material sampling `texture` with the uv coordinates of the mesh.
+    pub fn new_textured(texture: Rc<Texture>, engine: &Engine) -> Material {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.define("TEXTURED", "");
+        let attributes = [VertexAttribute::Position, VertexAttribute::Color(0), VertexAttribute::Normal, VertexAttribute::Uv];
+        Material::from_shader("default", &preprocessor, &attributes, None, vec![texture], engine)
+    }
 
-        let uniform_bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("Uniform Bind Group Layout"),
-        });
+    pub fn new_2d_terrain(max_height: f32, engine: &Engine) -> Material {
+        Material::new_terrain(&TerrainOptions { max_height, ..Default::default() }, engine)
+    }
 
-        let uniform_bind_group = engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: vertex_uniform_buffer.as_entire_binding(),
-            }],
-            label: Some("Uniform Bind Group"),
-        });
+    pub fn new_sphere_terrain(sphere_radius: f32, max_height: f32, engine: &Engine) -> Material {
+        Material::new_terrain(&TerrainOptions { max_height, sphere_radius: Some(sphere_radius), ..Default::default() }, engine)
+    }
 
-        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout],
-            push_constant_ranges: &[],
-        });
+    /// Same as `new_2d_terrain` with the flat colors replaced by detail textures, projected on the xz plane.
+    pub fn new_2d_terrain_textured(max_height: f32, textures: &TerrainTextures, engine: &Engine) -> Material {
+        Material::new_terrain(&TerrainOptions {
+            max_height,
+            textures: Some(textures.clone()),
+            ..Default::default()
+        }, engine)
+    }
 
-        let pipeline = engine.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: engine.config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24Plus,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
+    /// Same as `new_sphere_terrain` with the flat colors replaced by detail textures, projected on the xz plane.
+    pub fn new_sphere_terrain_textured(sphere_radius: f32, max_height: f32, textures: &TerrainTextures, engine: &Engine) -> Material {
+        Material::new_terrain(&TerrainOptions {
+            max_height,
+            sphere_radius: Some(sphere_radius),
+            textures: Some(textures.clone()),
+            ..Default::default()
+        }, engine)
+    }
 
-        Material {
-            shader_module: shader,
-            vertex_uniform_buffer,
-            uniform_bind_group_layout,
-            uniform_bind_group,
-            pipeline_layout,
-            pipeline,
-        }
+    /// Same as `new_2d_terrain_textured` with triplanar mapping, so steep slopes are not stretched.
+    pub fn new_2d_terrain_triplanar(max_height: f32, textures: &TerrainTextures, engine: &Engine) -> Material {
+        Material::new_terrain(&TerrainOptions {
+            max_height,
+            textures: Some(textures.clone()),
+            projection: TextureProjection::Triplanar,
+            ..Default::default()
+        }, engine)
     }
 
-    pub fn new_2d_terrain(max_height: f32, engine: &mut Engine) -> Material {
-        let shader = engine.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("./flat_terrain.wgsl").into()),
-        });
+    /// Same as `new_sphere_terrain_textured` with triplanar mapping, which covers the whole sphere without seams or poles.
+    pub fn new_sphere_terrain_triplanar(sphere_radius: f32, max_height: f32, textures: &TerrainTextures, engine: &Engine) -> Material {
+        Material::new_terrain(&TerrainOptions {
+            max_height,
+            sphere_radius: Some(sphere_radius),
+            textures: Some(textures.clone()),
+            projection: TextureProjection::Triplanar,
+            ..Default::default()
+        }, engine)
+    }
 
-        let mvp: Matrix4<f32> = Matrix4::identity();
-        let mvp_ref: &[f32; 16] = mvp.as_ref();
-        let vertex_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: cast_slice(mvp_ref),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    /// Terrain material with every option, see `TerrainOptions`.
+    /// With a normal map the mesh must have uvs, and tangents for a tangent space map.
+    pub fn new_terrain(options: &TerrainOptions, engine: &Engine) -> Material {
+        let mut preprocessor = ShaderPreprocessor::new();
+        match options.sphere_radius {
+            Some(_) => {
+                preprocessor.define("SPHERE_TERRAIN", "");
+                preprocessor.define("SNOW_START", "0.5");
+            }
+            None => preprocessor.define("SNOW_START", "0.7"),
+        }
 
-        // create fragment uniform buffer. here we set eye_position = camera_position and light_position = eye_position
-        let fragment_uniform_buffer = engine.device.create_buffer(&wgpu::BufferDescriptor {
+        let surfaces = [options.grass_surface, options.rock_surface, options.snow_surface];
+        let uniforms = TerrainUniforms {
+            roughness: surfaces.map(|surface| surface.roughness),
+            metallic: surfaces.map(|surface| surface.metallic),
+            max_height: options.max_height,
+            sphere_radius: options.sphere_radius.unwrap_or(0.0),
+            texture_scale: options.textures.as_ref().map_or(1.0, |textures| textures.scale),
+            triplanar_sharpness: 4.0,
+            normal_map_strength: options.normal_map.as_ref().map_or(0.0, |normal_map| normal_map.strength),
+            normal_map_scale: options.normal_map.as_ref().map_or(1.0, |normal_map| normal_map.scale),
+        };
+        let fragment_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Fragment Uniform Buffer"),
-            size: 32,
+            contents: cast_slice(&[uniforms]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
         });
 
-        // store light and eye positions
-        let light_dir: &[f32; 3] = &[1.0, 1.0, 0.5];
-        let camera_position: &[f32; 3] = &[0.0, 0.0, 0.0];
-        engine.queue.write_buffer(&fragment_uniform_buffer, 0, cast_slice(light_dir));
-        engine.queue.write_buffer(&fragment_uniform_buffer, 12, cast_slice(camera_position));
-        engine.queue.write_buffer(&fragment_uniform_buffer, 28, cast_slice(&max_height.to_ne_bytes()));
+        let mut material_textures = Vec::new();
+        if let Some(textures) = &options.textures {
+            preprocessor.define("TEXTURED", "");
+            if options.projection == TextureProjection::Triplanar {
+                preprocessor.define("TRIPLANAR", "");
+            }
+            material_textures.extend([textures.grass.clone(), textures.rock.clone(), textures.snow.clone()]);
+        }
 
-        let uniform_bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }, wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("Uniform Bind Group Layout"),
-        });
+        let mut attributes = BASE_ATTRIBUTES.to_vec();
+        if let Some(normal_map) = &options.normal_map {
+            add_normal_map(normal_map, &mut preprocessor, &mut attributes, &mut material_textures);
+        }
 
-        let uniform_bind_group = engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: vertex_uniform_buffer.as_entire_binding(),
-            }, wgpu::BindGroupEntry {
-                binding: 1,
-                resource: fragment_uniform_buffer.as_entire_binding(),
-            }],
-            label: Some("Uniform Bind Group"),
-        });
+        Material::from_shader("terrain", &preprocessor, &attributes, Some(fragment_uniform_buffer), material_textures, engine)
+    }
 
-        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout],
-            push_constant_ranges: &[],
-        });
+    /// Physically based material with uniform or textured base color, for any mesh.
+    /// With a base color texture or a normal map the mesh must have uvs, and tangents for a tangent space map.
+    pub fn new_pbr(options: &PbrOptions, engine: &Engine) -> Material {
+        let mut preprocessor = ShaderPreprocessor::new();
+        let mut attributes = BASE_ATTRIBUTES.to_vec();
+        let mut textures = Vec::new();
 
-        let pipeline = engine.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: engine.config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24Plus,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+        let [r, g, b] = options.base_color;
+        let uniforms = StandardUniforms {
+            base_color: [r, g, b, 1.0],
+            metallic: options.surface.metallic,
+            roughness: options.surface.roughness,
+            normal_map_strength: options.normal_map.as_ref().map_or(0.0, |normal_map| normal_map.strength),
+            normal_map_scale: options.normal_map.as_ref().map_or(1.0, |normal_map| normal_map.scale),
+        };
+        let fragment_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fragment Uniform Buffer"),
+            contents: cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        Material {
-            shader_module: shader,
-            vertex_uniform_buffer,
-            uniform_bind_group_layout,
-            uniform_bind_group,
-            pipeline_layout,
-            pipeline,
+        if let Some(texture) = &options.base_color_texture {
+            preprocessor.define("BASE_COLOR_TEXTURE", "");
+            attributes.push(VertexAttribute::Uv);
+            textures.push(texture.clone());
+        }
+        if let Some(normal_map) = &options.normal_map {
+            add_normal_map(normal_map, &mut preprocessor, &mut attributes, &mut textures);
         }
-    }
 
-    pub fn new_sphere_terrain(sphere_radius: f32, max_height: f32, engine: &mut Engine) -> Material {
-        let shader = engine.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("./sphere_terrain.wgsl").into()),
-        });
+        Material::from_shader("standard", &preprocessor, &attributes, Some(fragment_uniform_buffer), textures, engine)
+    }
 
-        let mvp: Matrix4<f32> = Matrix4::identity();
-        let mvp_ref: &[f32; 16] = mvp.as_ref();
-        let vertex_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: cast_slice(mvp_ref),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+    /// Transparent water for the meshes made by `Mesh::new_water_plane` and `Mesh::new_ocean_sphere`,
+    /// tinted by the depth of the water above the meshes behind it, with waves, foam along the shores
+    /// and the reflection of the sky, made of the ambient light and the environment map of the scene.
+    pub fn new_water(options: &WaterOptions, engine: &Engine) -> Material {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.define("READS_SCENE_DEPTH", "");
 
-        // create fragment uniform buffer. here we set eye_position = camera_position and light_position = eye_position
-        let fragment_uniform_buffer = engine.device.create_buffer(&wgpu::BufferDescriptor {
+        let uniforms = WaterUniforms {
+            shallow_color: options.shallow_color,
+            absorption: options.absorption,
+            deep_color: options.deep_color,
+            roughness: options.roughness,
+            foam_color: options.foam_color,
+            foam_distance: options.foam_distance,
+            wave_strength: options.wave_strength,
+            wavelength: options.wavelength,
+            wave_speed: options.wave_speed,
+            _padding: 0.0,
+        };
+        let fragment_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Fragment Uniform Buffer"),
-            size: 48,
+            contents: cast_slice(&[uniforms]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
         });
 
-        // store light and eye positions
-        let light_dir: &[f32; 3] = &[1.0, 1.0, 0.5];
-        let camera_position: &[f32; 3] = &[0.0, 0.0, 0.0];
-        engine.queue.write_buffer(&fragment_uniform_buffer, 0, cast_slice(light_dir));
-        engine.queue.write_buffer(&fragment_uniform_buffer, 12, cast_slice(camera_position));
-        engine.queue.write_buffer(&fragment_uniform_buffer, 28, cast_slice(&max_height.to_ne_bytes()));
-        engine.queue.write_buffer(&fragment_uniform_buffer, 32, cast_slice(&sphere_radius.to_ne_bytes()));
+        Material::from_shader("water", &preprocessor, &BASE_ATTRIBUTES, Some(fragment_uniform_buffer), Vec::new(), engine)
+    }
 
-        let uniform_bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
+    /// Builds a material from the shader `shader_name`, preprocessed with the defines of `preprocessor`,
+    /// reading the vertex attributes `attributes` from the vertex buffers in slots 0 to n.
+    /// The optional `fragment_uniform_buffer` is bound at group 2, binding 0,
+    /// then the i-th texture at binding 1 + 2i and its sampler at binding 2 + 2i.
+    /// Shaders defining `READS_SCENE_DEPTH` get the depth buffer at group 3 and are blended with premultiplied alpha.
+    /// With hot reload, a shader of the shader directory that does not compile is replaced by the one embedded in the binary
+    /// until it is fixed, so only the embedded shaders must compile.
+    fn from_shader(shader_name: &str, preprocessor: &ShaderPreprocessor, attributes: &[VertexAttribute], fragment_uniform_buffer: Option<Buffer>, textures: Vec<Rc<Texture>>, engine: &Engine) -> Material {
+        let mut preprocessor = preprocessor.clone();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        if attributes.contains(&VertexAttribute::Uv) {
+            preprocessor.define("HAS_UV", "");
+        }
+        if attributes.contains(&VertexAttribute::Tangent) {
+            preprocessor.define("HAS_TANGENT", "");
+        }
+
+        let mut layout_entries = Vec::new();
+        let mut entries = Vec::new();
+        if let Some(fragment_uniform_buffer) = &fragment_uniform_buffer {
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
+                visibility: wgpu::ShaderStages::FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
                 count: None,
-            }, wgpu::BindGroupLayoutEntry {
-                binding: 1,
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 0,
+                resource: fragment_uniform_buffer.as_entire_binding(),
+            });
+        }
+        for (i, texture) in textures.iter().enumerate() {
+            let binding = 1 + 2 * i as u32;
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding,
                 visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
                 },
                 count: None,
-            }],
-            label: Some("Uniform Bind Group Layout"),
+            });
+            layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            });
+        }
+
+        let uniform_bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &layout_entries,
+            label: Some("Material Bind Group Layout"),
         });
 
         let uniform_bind_group = engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: vertex_uniform_buffer.as_entire_binding(),
-            }, wgpu::BindGroupEntry {
-                binding: 1,
-                resource: fragment_uniform_buffer.as_entire_binding(),
-            }],
-            label: Some("Uniform Bind Group"),
+            entries: &entries,
+            label: Some("Material Bind Group"),
         });
 
+        let reads_scene_depth = preprocessor.is_defined("READS_SCENE_DEPTH");
+        let mut bind_group_layouts = vec![&engine.layouts.scene, &engine.layouts.mesh, &uniform_bind_group_layout];
+        let mut settings = PipelineSettings::default();
+        if reads_scene_depth {
+            bind_group_layouts.push(&engine.layouts.scene_depth);
+            settings.depth_format = None;
+            settings.multisampled = false;
+            settings.blend = wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING;
+        }
+
         let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&uniform_bind_group_layout],
+            bind_group_layouts: &bind_group_layouts,
             push_constant_ranges: &[],
         });
 
-        let pipeline = engine.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: engine.config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                ..Default::default()
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth24Plus,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
+        let vertex_layouts = VertexBufferLayouts::new(attributes);
+        let (shader_module, pipeline) = match create_pipeline(shader_name, &preprocessor, &pipeline_layout, &vertex_layouts.desc(), &settings, engine) {
+            Ok(compiled) => compiled,
+            Err(e) if preprocessor.shader_dir.is_some() => {
+                eprintln!("Could not create shader \"{}\" from the shader directory, using the embedded one: {}", shader_name, e);
+                let embedded = ShaderPreprocessor { shader_dir: None, ..preprocessor.clone() };
+                create_pipeline(shader_name, &embedded, &pipeline_layout, &vertex_layouts.desc(), &settings, engine)
+                    .unwrap_or_else(|e| panic!("Could not create shader \"{}\": {}", shader_name, e))
+            }
+            Err(e) => panic!("Could not create shader \"{}\": {}", shader_name, e),
+        };
+
+        let identity: [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
+        let vertex_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Uniform Buffer"),
+            contents: cast_slice(&identity),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        #[allow(deprecated)]
         Material {
-            shader_module: shader,
+            shader_name: shader_name.to_string(),
+            preprocessor,
+            settings,
+            vertex_layouts,
+            shader_module,
             vertex_uniform_buffer,
+            fragment_uniform_buffer,
+            textures,
             uniform_bind_group_layout,
             uniform_bind_group,
             pipeline_layout,
             pipeline,
+            reads_scene_depth,
         }
     }
 
+    /// Reloads the shader of the material (from disk if the engine has a shader directory) and rebuilds its pipeline.
+    /// If the shader does not compile, the previous pipeline is kept and the error is returned.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        self.preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        let (shader_module, pipeline) = self.compile(engine)?;
+        self.shader_module = shader_module;
+        self.pipeline = pipeline;
+
+        Ok(())
+    }
+
+    /// Compiles the shader of the material again into a new pipeline, leaving the material untouched.
+    /// Used to reload materials shared between meshes, which cannot be modified in place.
+    pub fn compile(&self, engine: &Engine) -> Result<(ShaderModule, RenderPipeline), ShaderError> {
+        let mut preprocessor = self.preprocessor.clone();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        create_pipeline(&self.shader_name, &preprocessor, &self.pipeline_layout, &self.vertex_layouts.desc(), &self.settings, engine)
+    }
+
     pub fn bind<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
         render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.uniform_bind_group, &[]);
+    }
+
+    /// Compiles the shader of the material with `INSTANCED` defined, reading the instance buffer of instanced meshes
+    /// after the vertex buffers. The pipeline is used with the bind groups of the material.
+    pub fn create_instanced_pipeline(&self, engine: &Engine) -> Result<RenderPipeline, ShaderError> {
+        let mut preprocessor = self.preprocessor.clone();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        preprocessor.define("INSTANCED", "");
+        let mut vertex_buffers = self.vertex_layouts.desc();
+        vertex_buffers.push(instance_buffer_layout());
+        let (_, pipeline) = create_pipeline(&self.shader_name, &preprocessor, &self.pipeline_layout, &vertex_buffers, &self.settings, engine)?;
+        Ok(pipeline)
+    }
+}
+
+/// Enables `normal_map` in a shader including the `normal_mapping` chunk: defines, vertex attributes and texture,
+/// added after the textures already in `textures`.
+fn add_normal_map(normal_map: &NormalMap, preprocessor: &mut ShaderPreprocessor, attributes: &mut Vec<VertexAttribute>, textures: &mut Vec<Rc<Texture>>) {
+    preprocessor.define("NORMAL_MAP", "");
+    if !attributes.contains(&VertexAttribute::Uv) {
+        attributes.push(VertexAttribute::Uv);
+    }
+    match normal_map.space {
+        NormalMapSpace::Tangent => attributes.push(VertexAttribute::Tangent),
+        NormalMapSpace::Object => preprocessor.define("OBJECT_SPACE_NORMAL_MAP", ""),
     }
-}
\ No newline at end of file
+    textures.push(normal_map.texture.clone());
+}
+
+/// Preprocesses and compiles `shader_name` and creates a render pipeline using it, reading vertices laid out as `vertex_buffers`.
+/// Validation errors are captured in an error scope and returned instead of aborting.
+pub(crate) fn create_pipeline(shader_name: &str, preprocessor: &ShaderPreprocessor, pipeline_layout: &PipelineLayout, vertex_buffers: &[wgpu::VertexBufferLayout], settings: &PipelineSettings, engine: &Engine) -> Result<(ShaderModule, RenderPipeline), ShaderError> {
+    let source = preprocessor.process(shader_name)?;
+    create_pipeline_from_source(shader_name, source, pipeline_layout, vertex_buffers, settings, engine)
+}
+
+/// Same as `create_pipeline` for already preprocessed WGSL, `label` naming the shader and the pipeline.
+pub(crate) fn create_pipeline_from_source(label: &str, source: String, pipeline_layout: &PipelineLayout, vertex_buffers: &[wgpu::VertexBufferLayout], settings: &PipelineSettings, engine: &Engine) -> Result<(ShaderModule, RenderPipeline), ShaderError> {
+    engine.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let color_targets: Vec<_> = settings.color_format.iter().map(|&format| Some(wgpu::ColorTargetState {
+        format,
+        blend: Some(settings.blend),
+        write_mask: wgpu::ColorWrites::ALL,
+    })).collect();
+
+    let shader = engine.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+
+    let pipeline = engine.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: vertex_buffers,
+        },
+        fragment: (!settings.depth_only).then_some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &color_targets,
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: settings.topology,
+            polygon_mode: settings.polygon_mode,
+            ..Default::default()
+        },
+        depth_stencil: settings.depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: settings.depth_write_enabled,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: settings.depth_bias,
+        }),
+        multisample: wgpu::MultisampleState {
+            count: if settings.multisampled { engine.sample_count() } else { 1 },
+            ..Default::default()
+        },
+        multiview: None,
+    });
+
+    match pollster::block_on(engine.device.pop_error_scope()) {
+        None => Ok((shader, pipeline)),
+        Some(error) => Err(ShaderError::Compilation(error.to_string())),
+    }
+}
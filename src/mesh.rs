@@ -1,89 +1,261 @@
-use std::mem;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
 use std::rc::Rc;
 
 use bytemuck::{cast_slice, Pod, Zeroable};
-use wgpu::{Buffer, RenderPass};
+use cgmath::{InnerSpace, Vector3};
+use wgpu::{BindGroup, Buffer, RenderPass, RenderPipeline};
 use wgpu::util::DeviceExt;
 use crate::engine::Engine;
 
 use crate::transform::Transform;
+use crate::debug::MeshDebugOptions;
 use crate::material::Material;
+use crate::render_mode::RenderMode;
+use crate::uniforms::MeshUniforms;
+use crate::vertex::{CustomAttribute, VertexAttribute};
 
+/// Layer of new meshes, see `Mesh::layers`.
+pub const DEFAULT_LAYER: u32 = 1;
+
+/// Interleaved vertex of the former fixed layout, each attribute padded to 4 components.
+#[deprecated(note = "meshes store each attribute in its own packed buffer, see `MeshData` and `VertexBufferLayouts`")]
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug)]
 pub struct Vertex {
     pub position: [f32; 4],
     pub color: [f32; 4],
     pub normal: [f32; 4],
 }
 
-impl Vertex {
-    const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![0=>Float32x4, 1=>Float32x4, 2=>Float32x4];
-    pub(crate) fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::ATTRIBUTES,
-        }
+// implemented by hand, the derived impls would use the deprecated struct
+#[allow(deprecated)]
+unsafe impl Zeroable for Vertex {}
+#[allow(deprecated)]
+unsafe impl Pod for Vertex {}
+
+/// Error of `Mesh::set_material`: the shader of the material reads a vertex attribute the mesh does not have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingVertexAttribute {
+    pub shader_name: String,
+    pub attribute: VertexAttribute,
+}
+
+impl fmt::Display for MissingVertexAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the material \"{}\" reads the vertex attribute {:?} that the mesh does not have", self.shader_name, self.attribute)
     }
 }
 
+impl std::error::Error for MissingVertexAttribute {}
+
+/// CPU side data used to build a mesh, only the indices and positions are required.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub indices: Vec<u32>,
+    pub positions: Vec<[f32; 3]>,
+    /// computed from the triangles when not given
+    pub normals: Option<Vec<[f32; 3]>>,
+    /// first color channel, uniform grey when not given
+    pub colors: Option<Vec<[f32; 3]>>,
+    pub uvs: Option<Vec<[f32; 2]>>,
+    /// computed from the uvs when not given and uvs are
+    pub tangents: Option<Vec<[f32; 4]>>,
+    /// color channels 1 and above
+    pub extra_colors: Vec<Vec<[f32; 4]>>,
+    pub custom_attributes: Vec<CustomAttribute>,
+}
+
 pub struct Mesh {
     pub transform: Transform,
     pub positions: Vec<[f32; 3]>,
     pub colors: Vec<[f32; 3]>,
     pub indices: Vec<u32>,
     pub normals: Vec<[f32; 3]>,
+    pub uvs: Option<Vec<[f32; 2]>>,
+    pub tangents: Option<Vec<[f32; 4]>>,
+    pub extra_colors: Vec<Vec<[f32; 4]>>,
+    pub custom_attributes: Vec<CustomAttribute>,
     pub index_buffer: Buffer,
-    pub vertex_buffer: Buffer,
+    /// one tightly packed buffer per vertex attribute
+    pub vertex_buffers: HashMap<VertexAttribute, Buffer>,
+    pub uniform_buffer: Buffer,
+    pub uniform_bind_group: BindGroup,
+    /// the attributes it reads are checked by `set_material` only, drawing panics if the mesh lacks one
     pub material: Rc<Material>,
+    /// overrides the render mode of the scene for this mesh
+    pub render_mode: Option<RenderMode>,
+    pub debug: MeshDebugOptions,
+    /// whether the mesh is drawn in the shadow maps of the sun
+    pub cast_shadows: bool,
+    /// bit mask of the layers the mesh belongs to, drawn by the cameras whose layers intersect it,
+    /// see `Scene::layers` and `RenderTexture::layers`
+    pub layers: u32,
 }
 
 impl Mesh {
-    pub fn from_vertex_data(indices: Vec<u32>, positions: Vec<[f32; 3]>, normals: Option<Vec<[f32; 3]>>, engine: &mut Engine) -> Mesh {
-        let colors = vec![[0.6, 0.6, 0.6]; positions.len()];
+    pub fn from_vertex_data(indices: Vec<u32>, positions: Vec<[f32; 3]>, normals: Option<Vec<[f32; 3]>>, engine: &Engine) -> Mesh {
+        Mesh::from_mesh_data(MeshData {
+            indices,
+            positions,
+            normals,
+            ..Default::default()
+        }, engine)
+    }
+
+    pub fn from_mesh_data(data: MeshData, engine: &Engine) -> Mesh {
+        let MeshData { indices, positions, normals, colors, uvs, tangents, extra_colors, custom_attributes } = data;
+
+        let colors = colors.unwrap_or_else(|| vec![[0.6, 0.6, 0.6]; positions.len()]);
         let normals = match normals {
             Some(v) => v,
             None => create_normals(&positions, &indices)
         };
+        let tangents = match (tangents, &uvs) {
+            (Some(tangents), _) => Some(tangents),
+            (None, Some(uvs)) => Some(create_tangents(&positions, &normals, uvs, &indices)),
+            (None, None) => None,
+        };
+
+        let mut vertex_buffers = HashMap::new();
+        let mut add_vertex_buffer = |attribute: VertexAttribute, contents: &[u8]| {
+            let buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents,
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+            vertex_buffers.insert(attribute, buffer);
+        };
+        add_vertex_buffer(VertexAttribute::Position, cast_slice(&positions));
+        add_vertex_buffer(VertexAttribute::Color(0), cast_slice(&colors));
+        add_vertex_buffer(VertexAttribute::Normal, cast_slice(&normals));
+        if let Some(uvs) = &uvs {
+            add_vertex_buffer(VertexAttribute::Uv, cast_slice(uvs));
+        }
+        if let Some(tangents) = &tangents {
+            add_vertex_buffer(VertexAttribute::Tangent, cast_slice(tangents));
+        }
+        for (i, channel) in extra_colors.iter().enumerate() {
+            add_vertex_buffer(VertexAttribute::Color(i as u32 + 1), cast_slice(channel));
+        }
+        for (i, custom_attribute) in custom_attributes.iter().enumerate() {
+            add_vertex_buffer(VertexAttribute::Custom { index: i as u32, components: custom_attribute.components }, cast_slice(&custom_attribute.data));
+        }
 
-        let vertex_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: cast_slice(&zip_vertex_data(&positions, &colors, &normals)),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
         let index_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
             contents: cast_slice(&indices),
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let (bounds_min, bounds_max) = compute_bounds(&positions);
+        let uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Uniform Buffer"),
+            contents: cast_slice(&[MeshUniforms::new(Transform::new().compute_world_matrix(), bounds_min, bounds_max)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_bind_group = engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &engine.layouts.mesh,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("Mesh Bind Group"),
+        });
+
         Mesh {
             transform: Transform::new(),
             positions,
-            vertex_buffer,
+            vertex_buffers,
             indices,
             index_buffer,
             colors,
             normals,
+            uvs,
+            tangents,
+            extra_colors,
+            custom_attributes,
+            uniform_buffer,
+            uniform_bind_group,
             material: Rc::new(Material::new_default(engine)),
+            render_mode: None,
+            debug: MeshDebugOptions::default(),
+            cast_shadows: true,
+            layers: DEFAULT_LAYER,
+        }
+    }
+
+    pub fn material(&self) -> &Rc<Material> {
+        &self.material
+    }
+
+    pub fn has_attribute(&self, attribute: VertexAttribute) -> bool {
+        self.vertex_buffers.contains_key(&attribute)
+    }
+
+    /// Replaces the material of the mesh, keeping the current one if the shader of the material reads a vertex attribute
+    /// the mesh does not have.
+    pub fn set_material(&mut self, material: Rc<Material>) -> Result<(), MissingVertexAttribute> {
+        if let Some(&attribute) = material.vertex_layouts.attributes.iter().find(|&&attribute| !self.has_attribute(attribute)) {
+            return Err(MissingVertexAttribute { shader_name: material.shader_name.clone(), attribute });
         }
+        self.material = material;
+        Ok(())
+    }
+
+    /// Returns the object space axis-aligned bounding box of the mesh as (min, max).
+    pub fn bounding_box(&self) -> ([f32; 3], [f32; 3]) {
+        compute_bounds(&self.positions)
+    }
+
+    /// Uploads the world matrix of the mesh to its uniform buffer.
+    pub fn update_uniforms(&self, engine: &Engine) {
+        let (bounds_min, bounds_max) = self.bounding_box();
+        let uniforms = MeshUniforms::new(self.transform.compute_world_matrix(), bounds_min, bounds_max);
+        engine.queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniforms]));
     }
 
     pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
         self.material.bind(render_pass);
+        self.draw_geometry(&self.material.vertex_layouts.attributes, render_pass);
+    }
+
+    /// Draws the mesh with its material bound but another pipeline built from the same shader, such as a reloaded one.
+    pub fn draw_with_material_pipeline<'a>(&'a self, pipeline: &'a RenderPipeline, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(2, &self.material.uniform_bind_group, &[]);
+        self.draw_geometry(&self.material.vertex_layouts.attributes, render_pass);
+    }
+
+    /// Draws the mesh with a pipeline other than the one of its material, such as a debug pipeline.
+    /// The pipeline is expected to use the camera and mesh bind groups only and to read `attributes`.
+    pub fn draw_with_pipeline<'a>(&'a self, pipeline: &'a RenderPipeline, attributes: &[VertexAttribute], render_pass: &mut RenderPass<'a>) {
+        render_pass.set_pipeline(pipeline);
+        self.draw_geometry(attributes, render_pass);
+    }
 
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+    fn draw_geometry<'a>(&'a self, attributes: &[VertexAttribute], render_pass: &mut RenderPass<'a>) {
+        self.draw_instances(attributes, 0..1, render_pass);
+    }
+
+    /// Binds the mesh uniforms and the vertex buffers of `attributes`, then draws `instances` copies of the mesh.
+    /// The pipeline, and any instance buffer, are expected to be bound already.
+    pub(crate) fn draw_instances<'a>(&'a self, attributes: &[VertexAttribute], instances: Range<u32>, render_pass: &mut RenderPass<'a>) {
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        for (slot, attribute) in attributes.iter().enumerate() {
+            render_pass.set_vertex_buffer(slot as u32, self.vertex_buffers[attribute].slice(..));
+        }
         render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        render_pass.draw_indexed(0..self.indices.len() as u32, 0, 0..1);
+        render_pass.draw_indexed(0..self.indices.len() as u32, 0, instances);
     }
 }
 
-pub fn create_normals(positions: &Vec<[f32; 3]>, indices: &Vec<u32>) -> Vec<[f32; 3]> {
+pub fn create_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
     let mut normals = vec![[0.0, 0.0, 0.0]; positions.len()];
 
     for i in 0..indices.len() / 3 {
-        let i0 = indices[i * 3 + 0] as usize;
+        let i0 = indices[i * 3] as usize;
         let i1 = indices[i * 3 + 1] as usize;
         let i2 = indices[i * 3 + 2] as usize;
 
@@ -127,14 +299,74 @@ pub fn create_normals(positions: &Vec<[f32; 3]>, indices: &Vec<u32>) -> Vec<[f32
     normals
 }
 
-pub fn zip_vertex_data(positions: &[[f32; 3]], colors: &[[f32; 3]], normals: &[[f32; 3]]) -> Vec<Vertex> {
-    let mut data: Vec<Vertex> = Vec::with_capacity(positions.len());
-    for i in 0..positions.len() {
-        data.push(Vertex {
-            position: [positions[i][0], positions[i][1], positions[i][2], 1.0],
-            color: [colors[i][0], colors[i][1], colors[i][2], 1.0],
-            normal: [normals[i][0], normals[i][1], normals[i][2], 1.0],
-        });
+pub fn compute_bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    if positions.is_empty() {
+        return ([0.0; 3], [0.0; 3]);
+    }
+
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for position in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
     }
-    data.to_vec()
-}
\ No newline at end of file
+
+    (min, max)
+}
+
+/// Computes per-vertex tangents from the uv mapping, orthogonalized against the normals.
+/// The w component is the handedness of the bitangent, to be used as `cross(normal, tangent.xyz) * tangent.w`.
+pub fn create_tangents(positions: &[[f32; 3]], normals: &[[f32; 3]], uvs: &[[f32; 2]], indices: &[u32]) -> Vec<[f32; 4]> {
+    let mut tangents = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+    let mut bitangents = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+
+        let edge1 = Vector3::from(positions[i1]) - Vector3::from(positions[i0]);
+        let edge2 = Vector3::from(positions[i2]) - Vector3::from(positions[i0]);
+        let delta_uv1 = [uvs[i1][0] - uvs[i0][0], uvs[i1][1] - uvs[i0][1]];
+        let delta_uv2 = [uvs[i2][0] - uvs[i0][0], uvs[i2][1] - uvs[i0][1]];
+
+        let determinant = delta_uv1[0] * delta_uv2[1] - delta_uv2[0] * delta_uv1[1];
+        if determinant.abs() < f32::EPSILON { continue; }
+        let r = 1.0 / determinant;
+
+        let tangent = (edge1 * delta_uv2[1] - edge2 * delta_uv1[1]) * r;
+        let bitangent = (edge2 * delta_uv1[0] - edge1 * delta_uv2[0]) * r;
+
+        for i in [i0, i1, i2] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    tangents.iter().zip(bitangents.iter()).zip(normals.iter()).map(|((tangent, bitangent), normal)| {
+        let normal = Vector3::from(*normal);
+        // Gram-Schmidt orthogonalization, falling back to any vector orthogonal to the normal
+        let mut orthogonal = tangent - normal * normal.dot(*tangent);
+        if orthogonal.magnitude2() < f32::EPSILON {
+            let helper = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            orthogonal = normal.cross(helper);
+        }
+        let orthogonal = orthogonal.normalize();
+        let handedness = if normal.cross(orthogonal).dot(*bitangent) < 0.0 { -1.0 } else { 1.0 };
+
+        [orthogonal.x, orthogonal.y, orthogonal.z, handedness]
+    }).collect()
+}
+
+/// Interleaves positions, colors and normals into the former fixed vertex layout, padding each with 1.0.
+#[deprecated(note = "meshes store each attribute in its own packed buffer, see `MeshData`")]
+#[allow(deprecated)]
+pub fn zip_vertex_data(positions: &[[f32; 3]], colors: &[[f32; 3]], normals: &[[f32; 3]]) -> Vec<Vertex> {
+    positions.iter().zip(colors).zip(normals)
+        .map(|((position, color), normal)| Vertex {
+            position: [position[0], position[1], position[2], 1.0],
+            color: [color[0], color[1], color[2], 1.0],
+            normal: [normal[0], normal[1], normal[2], 1.0],
+        })
+        .collect()
+}
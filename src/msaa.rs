@@ -0,0 +1,92 @@
+use std::path::Path;
+use wgpu::{BindGroupLayout, CommandEncoder, PipelineLayout, RenderPipeline, TextureView};
+use crate::engine::Engine;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::shader::{ShaderError, ShaderPreprocessor};
+
+/// Copies the multisampled depth buffer of the main pass into a single sampled one, since wgpu only resolves colors.
+/// The passes after the main pass, such as the fog or the post-processing, read and test against the resolved depth.
+pub struct DepthResolver {
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline: Option<RenderPipeline>,
+}
+
+impl DepthResolver {
+    pub fn new(engine: &Engine) -> DepthResolver {
+        let bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                count: None,
+            }],
+            label: Some("Depth Resolve Bind Group Layout"),
+        });
+
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Resolve Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut resolver = DepthResolver {
+            bind_group_layout,
+            pipeline_layout,
+            pipeline: None,
+        };
+        if let Err(e) = resolver.recompile(engine) {
+            eprintln!("Could not create shader \"depth_resolve\", the passes after the main pass will not see the meshes: {}", e);
+        }
+        resolver
+    }
+
+    /// Rebuilds the pipeline, keeping the previous one on errors.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        preprocessor.define("SAMPLE_COUNT", &engine.sample_count().to_string());
+        let settings = PipelineSettings {
+            color_format: None,
+            multisampled: false,
+            ..Default::default()
+        };
+        let (_, pipeline) = create_pipeline("depth_resolve", &preprocessor, &self.pipeline_layout, &[], &settings, engine)?;
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    /// Writes the closest sample of each pixel of `multisampled_view` into `depth_view`.
+    pub(crate) fn resolve(&self, encoder: &mut CommandEncoder, multisampled_view: &TextureView, depth_view: &TextureView, engine: &Engine) {
+        let bind_group = engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(multisampled_view),
+            }],
+            label: Some("Depth Resolve Bind Group"),
+        });
+
+        let mut resolve_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        if let Some(pipeline) = &self.pipeline {
+            resolve_pass.set_pipeline(pipeline);
+            resolve_pass.set_bind_group(0, &bind_group, &[]);
+            resolve_pass.draw(0..3, 0..1);
+        }
+    }
+}
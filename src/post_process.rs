@@ -0,0 +1,659 @@
+use std::path::Path;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, CommandEncoder, PipelineLayout, RenderPass, RenderPipeline, Sampler, TextureView};
+use crate::engine::Engine;
+use crate::material::{create_pipeline, create_pipeline_from_source, PipelineSettings};
+use crate::render_graph::{RenderGraph, TextureId, TransientTexture, ViewTextures};
+use crate::render_target::{BLOOM_LEVELS, OCCLUSION_FORMAT};
+use crate::render_texture::RENDER_TEXTURE_FORMAT;
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::uniforms::depth_texture_entry;
+
+/// Format of the color buffer the scene is rendered into, and of the buffers between post-process passes.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Adds the colors scaled by the blend constant, set by the bloom to its intensity.
+const ADDITIVE_BLENDING: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Constant,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Multiplies the colors, used to darken the scene with the ambient occlusion.
+const MULTIPLY_BLENDING: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::Src,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Curve mapping the HDR colors of the scene to the [0, 1] range of the screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneMappingOperator {
+    /// colors over 1 are clamped
+    None,
+    Reinhard,
+    /// filmic curve of the Academy Color Encoding System
+    Aces,
+}
+
+/// Exposure, tone mapping and gamma correction, turning the linear HDR colors into display colors.
+/// Passes after it work on gamma corrected colors in [0, 1], which suits FXAA.
+#[derive(Debug, Copy, Clone)]
+pub struct ToneMapping {
+    pub operator: ToneMappingOperator,
+    /// multiplies the colors before the tone mapping curve
+    pub exposure: f32,
+    /// gamma of the display, 1 to keep linear colors
+    pub gamma: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            operator: ToneMappingOperator::Aces,
+            exposure: 1.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+/// Color adjustments applied to the linear HDR colors, so it goes before the tone mapping.
+#[derive(Debug, Copy, Clone)]
+pub struct ColorGrading {
+    /// 1 keeps the colors, higher values push them away from middle gray
+    pub contrast: f32,
+    /// 0 for grayscale, 1 keeps the colors
+    pub saturation: f32,
+    /// multiplies the colors
+    pub tint: [f32; 3],
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        ColorGrading {
+            contrast: 1.0,
+            saturation: 1.0,
+            tint: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// Glow around the bright parts of the image, such as the sun highlights on the snow or the water.
+/// Works on HDR colors, so it goes before the tone mapping. Not in the default chain, see `PostProcessChain::insert`.
+#[derive(Debug, Copy, Clone)]
+pub struct Bloom {
+    /// brightness above which colors glow, the HDR colors of lit surfaces being around 1
+    pub threshold: f32,
+    /// range below the threshold where colors start to glow, for a smooth transition
+    pub knee: f32,
+    /// spread of the glow, 1 being the default blur
+    pub radius: f32,
+    /// strength of the glow added to the scene
+    pub intensity: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.0,
+            knee: 0.5,
+            radius: 1.0,
+            intensity: 0.3,
+        }
+    }
+}
+
+/// Screen space ambient occlusion, darkening the creases and the corners found from the depth buffer.
+/// Goes before the tone mapping, the fog and the atmosphere being already drawn it also darkens them slightly.
+/// Not in the default chain, as it samples the depth buffer many times per pixel, see `PostProcessChain::insert`.
+#[derive(Debug, Copy, Clone)]
+pub struct AmbientOcclusion {
+    /// world space radius of the hemisphere searched for occluders
+    pub radius: f32,
+    /// 0 for no occlusion, 1 for fully dark creases
+    pub intensity: f32,
+    /// depth difference in world units under which surfaces do not occlude, avoiding self occlusion on flat surfaces
+    pub bias: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            radius: 0.3,
+            intensity: 1.0,
+            bias: 0.02,
+        }
+    }
+}
+
+/// A post-process pass written in WGSL, with the same bindings as the built-in ones.
+/// The shader includes the `post_process` chunk, which also brings the `fullscreen` vertex shader,
+/// the scene uniforms and the depth buffer, and defines `fs_main(in: FullscreenOutput) -> @location(0) vec4<f32>`
+/// reading the previous pass with `input_color(uv)`.
+/// `uniforms` are bound at group 1, binding 0, padded to 16 bytes.
+#[derive(Debug, Clone)]
+pub struct CustomPass {
+    pub name: String,
+    pub source: String,
+    pub uniforms: Vec<u8>,
+}
+
+/// An effect of the post-process chain.
+#[derive(Debug, Clone)]
+pub enum PostEffect {
+    ToneMapping(ToneMapping),
+    ColorGrading(ColorGrading),
+    Bloom(Bloom),
+    AmbientOcclusion(AmbientOcclusion),
+    /// fast approximate anti-aliasing, to place after the tone mapping
+    Fxaa,
+    Custom(CustomPass),
+}
+
+/// Uniforms of `tone_mapping.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ToneMappingUniforms {
+    curve: u32,
+    exposure: f32,
+    gamma: f32,
+    _padding: f32,
+}
+
+/// Uniforms of `color_grading.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ColorGradingUniforms {
+    tint: [f32; 3],
+    contrast: f32,
+    saturation: f32,
+    _padding: [f32; 3],
+}
+
+/// Uniforms of `bloom.wgsl`, the intensity being the blend constant of the last upsample.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct BloomUniforms {
+    threshold: f32,
+    knee: f32,
+    radius: f32,
+    _padding: f32,
+}
+
+/// Uniforms of `ssao.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct AmbientOcclusionUniforms {
+    radius: f32,
+    intensity: f32,
+    bias: f32,
+    _padding: f32,
+}
+
+/// A fullscreen draw of an effect, most effects having a single one.
+struct Stage {
+    defines: &'static [&'static str],
+    color_format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+}
+
+impl PostEffect {
+    /// Name of the shader of the effect, also used to find its pass with `PostProcessChain::pass_mut`.
+    pub fn name(&self) -> &str {
+        match self {
+            PostEffect::ToneMapping(_) => "tone_mapping",
+            PostEffect::ColorGrading(_) => "color_grading",
+            PostEffect::Bloom(_) => "bloom",
+            PostEffect::AmbientOcclusion(_) => "ssao",
+            PostEffect::Fxaa => "fxaa",
+            PostEffect::Custom(custom) => &custom.name,
+        }
+    }
+
+    /// Contents of the uniform buffer of the pass, at least 16 bytes long.
+    fn uniforms(&self) -> Vec<u8> {
+        let mut bytes = match self {
+            PostEffect::ToneMapping(tone_mapping) => cast_slice(&[ToneMappingUniforms {
+                curve: tone_mapping.operator as u32,
+                exposure: tone_mapping.exposure,
+                gamma: tone_mapping.gamma,
+                _padding: 0.0,
+            }]).to_vec(),
+            PostEffect::ColorGrading(color_grading) => cast_slice(&[ColorGradingUniforms {
+                tint: color_grading.tint,
+                contrast: color_grading.contrast,
+                saturation: color_grading.saturation,
+                _padding: [0.0; 3],
+            }]).to_vec(),
+            PostEffect::Bloom(bloom) => cast_slice(&[BloomUniforms {
+                threshold: bloom.threshold,
+                knee: bloom.knee.max(0.0),
+                radius: bloom.radius,
+                _padding: 0.0,
+            }]).to_vec(),
+            PostEffect::AmbientOcclusion(occlusion) => cast_slice(&[AmbientOcclusionUniforms {
+                radius: occlusion.radius,
+                intensity: occlusion.intensity,
+                bias: occlusion.bias,
+                _padding: 0.0,
+            }]).to_vec(),
+            PostEffect::Fxaa => Vec::new(),
+            PostEffect::Custom(custom) => custom.uniforms.clone(),
+        };
+        bytes.resize(bytes.len().max(16).next_multiple_of(16), 0);
+        bytes
+    }
+
+    /// Draws of the effect, in the order of their pipelines in `PostProcessPass`.
+    /// Effects with several stages draw into their own textures and blend their result over their input,
+    /// see `PostProcessChain::add_bloom_pass` and `PostProcessChain::add_ambient_occlusion_pass`.
+    fn stages(&self) -> Vec<Stage> {
+        let stage = |defines, color_format, blend| Stage { defines, color_format, blend };
+        match self {
+            PostEffect::Bloom(_) => vec![
+                stage(&["BLOOM_PREFILTER"], HDR_FORMAT, wgpu::BlendState::REPLACE),
+                stage(&[], HDR_FORMAT, wgpu::BlendState::REPLACE),
+                stage(&["BLOOM_UPSAMPLE"], HDR_FORMAT, ADDITIVE_BLENDING),
+            ],
+            PostEffect::AmbientOcclusion(_) => vec![
+                stage(&[], OCCLUSION_FORMAT, wgpu::BlendState::REPLACE),
+                stage(&["SSAO_COMPOSITE"], HDR_FORMAT, MULTIPLY_BLENDING),
+            ],
+            _ => vec![stage(&[], HDR_FORMAT, wgpu::BlendState::REPLACE)],
+        }
+    }
+}
+
+/// A pass of the post-process chain. Its effect can be edited between frames, as long as it stays of the same kind.
+pub struct PostProcessPass {
+    pub effect: PostEffect,
+    /// disabled passes are skipped
+    pub enabled: bool,
+    uniform_buffer: Buffer,
+    /// in bytes
+    uniform_size: usize,
+    /// one per stage of the effect
+    pipelines: Vec<RenderPipeline>,
+}
+
+/// Fullscreen passes turning the HDR color buffer of the scene into the image on screen, run in order.
+/// Each pass reads the output of the previous one at group 1, see `CustomPass` and the `post_process` chunk.
+pub struct PostProcessChain {
+    pub passes: Vec<PostProcessPass>,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    sampler: Sampler,
+    /// copy the output of the last pass to the surface or to render textures, one per output format
+    present_pipelines: Vec<(wgpu::TextureFormat, RenderPipeline)>,
+    /// copies textures already in the format of the surface into a region of it, see `add_copy_pass`
+    copy_pipeline: Option<RenderPipeline>,
+    present_uniform_buffer: Buffer,
+}
+
+impl PostProcessChain {
+    /// Chain made of the default tone mapping followed by FXAA.
+    /// The ambient occlusion and the bloom are inserted before the tone mapping by the applications using them.
+    pub fn new(engine: &Engine) -> PostProcessChain {
+        let bind_group_layout = engine.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                depth_texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Post Process Bind Group Layout"),
+        });
+
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let sampler = engine.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let present_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Present Uniform Buffer"),
+            contents: &[0; 16],
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mut chain = PostProcessChain {
+            passes: Vec::new(),
+            bind_group_layout,
+            pipeline_layout,
+            sampler,
+            present_pipelines: Vec::new(),
+            copy_pipeline: None,
+            present_uniform_buffer,
+        };
+        chain.recompile(engine);
+        let effects = [
+            PostEffect::ToneMapping(ToneMapping::default()),
+            PostEffect::Fxaa,
+        ];
+        for effect in effects {
+            let name = effect.name().to_string();
+            chain.push(effect, engine).unwrap_or_else(|e| panic!("Could not create shader \"{}\": {}", name, e));
+        }
+        chain
+    }
+
+    /// Compiles the shader of `effect` into a pass, to be added to `passes`.
+    pub fn create_pass(&self, effect: PostEffect, engine: &Engine) -> Result<PostProcessPass, ShaderError> {
+        let pipelines = self.create_effect_pipelines(&effect, engine)?;
+        let uniforms = effect.uniforms();
+        let uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Process Uniform Buffer"),
+            contents: &uniforms,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Ok(PostProcessPass {
+            effect,
+            enabled: true,
+            uniform_buffer,
+            uniform_size: uniforms.len(),
+            pipelines,
+        })
+    }
+
+    /// Adds a pass at the end of the chain.
+    pub fn push(&mut self, effect: PostEffect, engine: &Engine) -> Result<(), ShaderError> {
+        let pass = self.create_pass(effect, engine)?;
+        self.passes.push(pass);
+        Ok(())
+    }
+
+    /// Adds a pass before the pass at `index`, panicking if `index` is greater than the number of passes.
+    pub fn insert(&mut self, index: usize, effect: PostEffect, engine: &Engine) -> Result<(), ShaderError> {
+        let pass = self.create_pass(effect, engine)?;
+        self.passes.insert(index, pass);
+        Ok(())
+    }
+
+    /// The first pass whose effect is named `name`, see `PostEffect::name`.
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut PostProcessPass> {
+        self.passes.iter_mut().find(|pass| pass.effect.name() == name)
+    }
+
+    fn create_effect_pipelines(&self, effect: &PostEffect, engine: &Engine) -> Result<Vec<RenderPipeline>, ShaderError> {
+        effect.stages().iter().map(|stage| {
+            let mut preprocessor = ShaderPreprocessor::new();
+            preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+            for define in stage.defines {
+                preprocessor.define(define, "");
+            }
+            let settings = PipelineSettings {
+                depth_format: None,
+                color_format: Some(stage.color_format),
+                blend: stage.blend,
+                multisampled: false,
+                ..Default::default()
+            };
+            let (_, pipeline) = match effect {
+                PostEffect::Custom(custom) => {
+                    let source = preprocessor.process_source(&custom.name, &custom.source)?;
+                    create_pipeline_from_source(&custom.name, source, &self.pipeline_layout, &[], &settings, engine)?
+                }
+                _ => create_pipeline(effect.name(), &preprocessor, &self.pipeline_layout, &[], &settings, engine)?,
+            };
+            Ok(pipeline)
+        }).collect()
+    }
+
+    /// Rebuilds the pipelines of the passes, printing the errors and keeping the previous pipelines on failure.
+    pub fn recompile(&mut self, engine: &Engine) {
+        let mut output_formats = vec![engine.surface_format];
+        if !output_formats.contains(&RENDER_TEXTURE_FORMAT) {
+            output_formats.push(RENDER_TEXTURE_FORMAT);
+        }
+        for format in output_formats {
+            let mut preprocessor = ShaderPreprocessor::new();
+            preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+            // the chain outputs display colors, that sRGB surfaces would encode a second time
+            if format.describe().srgb {
+                preprocessor.define("SRGB_OUTPUT", "");
+            }
+            let settings = PipelineSettings {
+                depth_format: None,
+                color_format: Some(format),
+                multisampled: false,
+                ..Default::default()
+            };
+            match create_pipeline("present", &preprocessor, &self.pipeline_layout, &[], &settings, engine) {
+                Ok((_, pipeline)) => {
+                    self.present_pipelines.retain(|(present_format, _)| *present_format != format);
+                    self.present_pipelines.push((format, pipeline));
+                }
+                Err(e) => eprintln!("Could not create shader \"present\" for {:?}, keeping the previous pipeline: {}", format, e),
+            }
+        }
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+        let settings = PipelineSettings {
+            depth_format: None,
+            color_format: Some(engine.surface_format),
+            multisampled: false,
+            ..Default::default()
+        };
+        match create_pipeline("present", &preprocessor, &self.pipeline_layout, &[], &settings, engine) {
+            Ok((_, pipeline)) => self.copy_pipeline = Some(pipeline),
+            Err(e) => eprintln!("Could not create shader \"present\" for copies, keeping the previous pipeline: {}", e),
+        }
+
+        for i in 0..self.passes.len() {
+            match self.create_effect_pipelines(&self.passes[i].effect, engine) {
+                Ok(pipelines) => self.passes[i].pipelines = pipelines,
+                Err(e) => eprintln!("Could not reload shader \"{}\", keeping the previous pipeline: {}", self.passes[i].effect.name(), e),
+            }
+        }
+    }
+
+    /// Uploads the settings of the effects, growing the uniform buffers of custom passes when needed.
+    pub(crate) fn update(&mut self, engine: &Engine) {
+        for pass in self.passes.iter_mut() {
+            let uniforms = pass.effect.uniforms();
+            if uniforms.len() != pass.uniform_size {
+                pass.uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Post Process Uniform Buffer"),
+                    contents: &uniforms,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                });
+                pass.uniform_size = uniforms.len();
+            } else {
+                engine.queue.write_buffer(&pass.uniform_buffer, 0, &uniforms);
+            }
+        }
+    }
+
+    /// Adds the enabled passes to `graph`, running on the color buffer of `view` and drawing the result into its output.
+    /// The color buffer is overwritten by the effects drawing over it, such as the bloom.
+    pub(crate) fn add_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, view: &ViewTextures, scene_bind_group: &'a BindGroup, engine: &'a Engine) {
+        let depth = view.depth;
+
+        // passes draw into new transient textures, the render graph aliasing them two by two
+        let mut input = view.hdr;
+        for pass in self.passes.iter().filter(|pass| pass.enabled) {
+            match pass.effect {
+                PostEffect::Bloom(bloom) => self.add_bloom_pass(graph, pass, bloom.intensity, input, view, scene_bind_group, engine),
+                PostEffect::AmbientOcclusion(_) => self.add_ambient_occlusion_pass(graph, pass, input, view, scene_bind_group, engine),
+                _ => {
+                    let output = graph.create_texture(TransientTexture { width: view.width, height: view.height, format: HDR_FORMAT });
+                    graph.add_pass(pass.effect.name(), &[input, depth], &[output], move |encoder, textures| {
+                        let bind_group = self.create_bind_group(&pass.uniform_buffer, textures.view(depth), textures.view(input), engine);
+                        begin_pass(encoder, "Post Process Pass", textures.view(output), wgpu::LoadOp::Clear(wgpu::Color::BLACK), &pass.pipelines[0], scene_bind_group, &bind_group).draw(0..3, 0..1);
+                    });
+                    input = output;
+                }
+            }
+        }
+
+        if let Some((_, present_pipeline)) = self.present_pipelines.iter().find(|(format, _)| *format == view.output_format) {
+            let output = view.output;
+            graph.add_pass("present", &[input, depth], &[output], move |encoder, textures| {
+                let bind_group = self.create_bind_group(&self.present_uniform_buffer, textures.view(depth), textures.view(input), engine);
+                begin_pass(encoder, "Present Pass", textures.view(output), wgpu::LoadOp::Clear(wgpu::Color::BLACK), present_pipeline, scene_bind_group, &bind_group).draw(0..3, 0..1);
+            });
+        }
+    }
+
+    /// Adds a pass copying the output of `view`, in the format of the surface, into the region of `target`
+    /// given by `rect`: left, top, width and height in pixels. Used to draw the viewports on the surface.
+    pub(crate) fn add_copy_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, view: &ViewTextures, target: TextureId, rect: [u32; 4], scene_bind_group: &'a BindGroup, engine: &'a Engine) {
+        if let Some(copy_pipeline) = &self.copy_pipeline {
+            let (input, depth) = (view.output, view.depth);
+            graph.add_pass("copy", &[input, depth], &[target], move |encoder, textures| {
+                let bind_group = self.create_bind_group(&self.present_uniform_buffer, textures.view(depth), textures.view(input), engine);
+                let mut render_pass = begin_pass(encoder, "Copy Pass", textures.view(target), wgpu::LoadOp::Load, copy_pipeline, scene_bind_group, &bind_group);
+                let [left, top, width, height] = rect;
+                render_pass.set_viewport(left as f32, top as f32, width as f32, height as f32, 0.0, 1.0);
+                render_pass.set_scissor_rect(left, top, width, height);
+                render_pass.draw(0..3, 0..1);
+            });
+        }
+    }
+
+    /// Blurs the bright parts of `target` through a chain of smaller textures and adds them back to it.
+    #[allow(clippy::too_many_arguments)]
+    fn add_bloom_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, pass: &'a PostProcessPass, intensity: f32, target: TextureId, view: &ViewTextures, scene_bind_group: &'a BindGroup, engine: &'a Engine) {
+        let depth = view.depth;
+        let mut levels = Vec::new();
+        let (mut width, mut height) = (view.width / 2, view.height / 2);
+        while levels.len() < BLOOM_LEVELS && width >= 1 && height >= 1 {
+            levels.push(graph.create_texture(TransientTexture { width, height, format: HDR_FORMAT }));
+            width /= 2;
+            height /= 2;
+        }
+
+        let mut writes = levels.clone();
+        writes.push(target);
+        graph.add_pass("bloom", &[target, depth], &writes, move |encoder, textures| {
+            let depth_view = textures.view(depth);
+            let target = textures.view(target);
+            let views: Vec<&TextureView> = levels.iter().map(|&level| textures.view(level)).collect();
+
+            let mut input = target;
+            for (i, view) in views.iter().enumerate() {
+                let pipeline = if i == 0 { &pass.pipelines[0] } else { &pass.pipelines[1] };
+                let bind_group = self.create_bind_group(&pass.uniform_buffer, depth_view, input, engine);
+                begin_pass(encoder, "Bloom Downsample Pass", view, wgpu::LoadOp::Clear(wgpu::Color::BLACK), pipeline, scene_bind_group, &bind_group).draw(0..3, 0..1);
+                input = view;
+            }
+
+            // each level gets the blurred levels below it, the largest one being added to the scene
+            for i in (0..views.len()).rev() {
+                let (output, weight) = if i == 0 { (target, intensity as f64) } else { (views[i - 1], 1.0) };
+                let bind_group = self.create_bind_group(&pass.uniform_buffer, depth_view, views[i], engine);
+                let mut render_pass = begin_pass(encoder, "Bloom Upsample Pass", output, wgpu::LoadOp::Load, &pass.pipelines[2], scene_bind_group, &bind_group);
+                render_pass.set_blend_constant(wgpu::Color { r: weight, g: weight, b: weight, a: weight });
+                render_pass.draw(0..3, 0..1);
+            }
+        });
+    }
+
+    /// Computes the occlusion of `target` into a texture, then darkens `target` with its blurred values.
+    fn add_ambient_occlusion_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, pass: &'a PostProcessPass, target: TextureId, view: &ViewTextures, scene_bind_group: &'a BindGroup, engine: &'a Engine) {
+        let depth = view.depth;
+        let occlusion = graph.create_texture(TransientTexture { width: view.width, height: view.height, format: OCCLUSION_FORMAT });
+
+        graph.add_pass("ssao", &[target, depth], &[occlusion, target], move |encoder, textures| {
+            let (depth_view, occlusion_view, target) = (textures.view(depth), textures.view(occlusion), textures.view(target));
+
+            let bind_group = self.create_bind_group(&pass.uniform_buffer, depth_view, target, engine);
+            begin_pass(encoder, "Ambient Occlusion Pass", occlusion_view, wgpu::LoadOp::Clear(wgpu::Color::WHITE), &pass.pipelines[0], scene_bind_group, &bind_group).draw(0..3, 0..1);
+
+            let bind_group = self.create_bind_group(&pass.uniform_buffer, depth_view, occlusion_view, engine);
+            begin_pass(encoder, "Ambient Occlusion Composite Pass", target, wgpu::LoadOp::Load, &pass.pipelines[1], scene_bind_group, &bind_group).draw(0..3, 0..1);
+        });
+    }
+
+    fn create_bind_group(&self, uniform_buffer: &Buffer, depth_view: &TextureView, input_view: &TextureView, engine: &Engine) -> BindGroup {
+        engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(input_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("Post Process Bind Group"),
+        })
+    }
+}
+
+/// Starts a fullscreen pass drawing into `output_view`, with the bind groups of the post-process passes.
+fn begin_pass<'a>(encoder: &'a mut CommandEncoder, label: &str, output_view: &'a TextureView, load: wgpu::LoadOp<wgpu::Color>, pipeline: &'a RenderPipeline, scene_bind_group: &'a BindGroup, bind_group: &'a BindGroup) -> RenderPass<'a> {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: output_view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, scene_bind_group, &[]);
+    render_pass.set_bind_group(1, bind_group, &[]);
+    render_pass
+}
@@ -2,18 +2,21 @@ use std::rc::Rc;
 use hexasphere::shapes::IcoSphere;
 use crate::engine::Engine;
 
-use crate::material::Material;
-use crate::mesh::Mesh;
+use crate::material::{Material, WaterOptions};
+use crate::mesh::{Mesh, MeshData};
 
 impl Mesh {
     /// Creates a new procedural 2D terrain.
-    /// It is made of a subdivided plane, with a given `size` and number of subdivisions (`nb_subdivisions`)
+    /// It is made of a subdivided plane, with a given `size` and number of subdivisions (`nb_subdivisions`, at least 2)
     /// The `height_fn` takes x and z as parameters and is used to set the y coordinate of each vertex.
     /// The `max_height` parameter is used to scale the y coordinate of each vertex in the range [0, 1]
-    /// `engine` is a mutable reference to the Gilgamesh engine.
+    /// `engine` is a reference to the Gilgamesh engine.
     /// It returns a Mesh that can be moved with its transform and with a default terrain material.
-    pub fn new_procedural_terrain(size: f32, nb_subdivisions: u32, height_fn: &dyn Fn(f32, f32) -> f32, max_height: f32, engine: &mut Engine) -> Mesh {
+    /// Its uv coordinates span [0, 1] over the whole plane and its tangents follow the x axis.
+    pub fn new_procedural_terrain(size: f32, nb_subdivisions: u32, height_fn: &dyn Fn(f32, f32) -> f32, max_height: f32, engine: &Engine) -> Mesh {
+        assert!(nb_subdivisions >= 2, "A procedural terrain needs at least 2 subdivisions per side, got {}", nb_subdivisions);
         let mut positions = vec!([0.0, 0.0, 0.0]; (nb_subdivisions * nb_subdivisions) as usize);
+        let mut uvs = vec!([0.0, 0.0]; (nb_subdivisions * nb_subdivisions) as usize);
         let mut indices = vec!(0; (6 * (nb_subdivisions - 1) * (nb_subdivisions - 1)) as usize);
 
         for x in 0..nb_subdivisions {
@@ -22,6 +25,7 @@ impl Mesh {
                 let actual_y = (y as f32 - (nb_subdivisions as f32 / 2.0)) * size / nb_subdivisions as f32;
 
                 positions[(x * nb_subdivisions + y) as usize] = [actual_x, height_fn(actual_x, actual_y), actual_y];
+                uvs[(x * nb_subdivisions + y) as usize] = [x as f32 / (nb_subdivisions - 1) as f32, y as f32 / (nb_subdivisions - 1) as f32];
 
                 if x == nb_subdivisions - 1 || y == nb_subdivisions - 1 { continue; }
 
@@ -35,8 +39,13 @@ impl Mesh {
             }
         }
 
-        let mut mesh = Mesh::from_vertex_data(indices, positions, None, engine);
-        mesh.material = Rc::from(Material::new_2d_terrain(max_height, engine));
+        let mut mesh = Mesh::from_mesh_data(MeshData {
+            indices,
+            positions,
+            uvs: Some(uvs),
+            ..Default::default()
+        }, engine);
+        mesh.set_material(Rc::from(Material::new_2d_terrain(max_height, engine))).expect("the material reads an attribute of the generated mesh");
 
         mesh
     }
@@ -45,8 +54,8 @@ impl Mesh {
     /// It is made of a subdivided icosahedron, with a given `diameter` and number of subdivisions (`nb_subdivisions`)
     /// The `height_fn` takes x, y and z as parameters and is used to set the height of each vertex above the surface of the sphere.
     /// The `max_height` parameter is used to scale the height of each vertex in the range [0, 1]
-    /// `engine` is a mutable reference to the Gilgamesh engine.
-    pub fn new_procedural_sphere(diameter: f32, nb_subdivisions: u32, height_fn: &dyn Fn(f32, f32, f32) -> f32, max_height: f32, engine: &mut Engine) -> Mesh {
+    /// `engine` is a reference to the Gilgamesh engine.
+    pub fn new_procedural_sphere(diameter: f32, nb_subdivisions: u32, height_fn: &dyn Fn(f32, f32, f32) -> f32, max_height: f32, engine: &Engine) -> Mesh {
         let sphere = IcoSphere::new(nb_subdivisions as usize, |_| ());
         let vertices_raw = sphere.raw_points();
         let mut vertices: Vec<[f32; 3]> = Vec::with_capacity(vertices_raw.len());
@@ -65,8 +74,55 @@ impl Mesh {
         let indices = sphere.get_all_indices();
 
         let mut mesh = Mesh::from_vertex_data(indices, vertices, None, engine);
-        mesh.material = Rc::from(Material::new_sphere_terrain(diameter / 2.0, max_height, engine));
+        mesh.set_material(Rc::from(Material::new_sphere_terrain(diameter / 2.0, max_height, engine))).expect("the material reads an attribute of the generated mesh");
 
         mesh
     }
-}
\ No newline at end of file
+
+    /// Creates a square sea of `size` at `height` for the terrains made by `new_procedural_terrain`, centered like them
+    /// and made of `nb_subdivisions` x `nb_subdivisions` vertices (at least 2), with a water material using `options`.
+    /// Its vertices lie on those of a terrain with the same `size` and `nb_subdivisions`.
+    /// It does not cast shadows.
+    pub fn new_water_plane(size: f32, height: f32, nb_subdivisions: u32, options: &WaterOptions, engine: &Engine) -> Mesh {
+        assert!(nb_subdivisions >= 2, "A water plane needs at least 2 subdivisions per side, got {}", nb_subdivisions);
+        let mut positions = Vec::with_capacity((nb_subdivisions * nb_subdivisions) as usize);
+        let mut indices = Vec::with_capacity((6 * (nb_subdivisions - 1) * (nb_subdivisions - 1)) as usize);
+
+        for x in 0..nb_subdivisions {
+            for z in 0..nb_subdivisions {
+                let actual_x = (x as f32 - (nb_subdivisions as f32 / 2.0)) * size / nb_subdivisions as f32;
+                let actual_z = (z as f32 - (nb_subdivisions as f32 / 2.0)) * size / nb_subdivisions as f32;
+                positions.push([actual_x, height, actual_z]);
+
+                if x == nb_subdivisions - 1 || z == nb_subdivisions - 1 { continue; }
+
+                indices.extend_from_slice(&[
+                    (x + 1) * nb_subdivisions + z, x * nb_subdivisions + z, x * nb_subdivisions + z + 1,
+                    (x + 1) * nb_subdivisions + z, x * nb_subdivisions + z + 1, (x + 1) * nb_subdivisions + z + 1,
+                ]);
+            }
+        }
+
+        let normals = vec![[0.0, 1.0, 0.0]; positions.len()];
+        let mut mesh = Mesh::from_vertex_data(indices, positions, Some(normals), engine);
+        mesh.set_material(Rc::from(Material::new_water(options, engine))).expect("the material reads an attribute of the generated mesh");
+        mesh.cast_shadows = false;
+
+        mesh
+    }
+
+    /// Creates the ocean of a planet made by `new_procedural_sphere`: a sphere of `radius` around the origin,
+    /// the sea level being the distance to the center, with a water material using `options`.
+    /// It does not cast shadows.
+    pub fn new_ocean_sphere(radius: f32, nb_subdivisions: u32, options: &WaterOptions, engine: &Engine) -> Mesh {
+        let sphere = IcoSphere::new(nb_subdivisions as usize, |_| ());
+        let normals: Vec<[f32; 3]> = sphere.raw_points().iter().map(|point| [point[0], point[1], point[2]]).collect();
+        let positions = normals.iter().map(|normal| [normal[0] * radius, normal[1] * radius, normal[2] * radius]).collect();
+
+        let mut mesh = Mesh::from_vertex_data(sphere.get_all_indices(), positions, Some(normals), engine);
+        mesh.set_material(Rc::from(Material::new_water(options, engine))).expect("the material reads an attribute of the generated mesh");
+        mesh.cast_shadows = false;
+
+        mesh
+    }
+}
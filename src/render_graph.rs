@@ -0,0 +1,371 @@
+use wgpu::{BindGroup, CommandEncoder, Device, TextureView};
+use crate::engine::Engine;
+use crate::render_target::RenderTarget;
+
+/// Handle of a texture of a `RenderGraph`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TextureId(usize);
+
+/// Description of a texture allocated by the render graph for the frame, see `RenderGraph::create_texture`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TransientTexture {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+}
+
+enum GraphTexture {
+    /// lives outside of the graph, such as the surface or the buffers of `RenderTargets`, with its index in `imported_views`
+    Imported(usize),
+    Transient(TransientTexture),
+}
+
+/// Records the commands of a pass.
+type ExecutePass<'a> = Box<dyn FnOnce(&mut CommandEncoder, &PassTextures) + 'a>;
+
+struct GraphPass<'a> {
+    label: String,
+    reads: Vec<TextureId>,
+    writes: Vec<TextureId>,
+    execute: ExecutePass<'a>,
+}
+
+/// The views of the textures a pass of a render graph declared, given to the pass when it is executed.
+pub struct PassTextures<'t> {
+    /// None for the transient textures no pass uses
+    views: &'t [Option<&'t TextureView>],
+    label: &'t str,
+    /// the reads and the writes of the pass
+    declared: Vec<TextureId>,
+}
+
+impl PassTextures<'_> {
+    /// Panics if `texture` is neither read nor written by the running pass.
+    pub fn view(&self, texture: TextureId) -> &TextureView {
+        assert!(self.declared.contains(&texture), "The texture {:?} is not declared by the pass \"{}\"", texture, self.label);
+        self.views[texture.0].expect("Declared textures are allocated")
+    }
+}
+
+/// The passes of a frame and the textures they read and write.
+///
+/// Passes are ordered from their declarations: the passes writing a texture, including the ones reading it too
+/// to draw over it, run in the order they were added, and the passes only reading it run after all of them.
+/// A pass drawing over the color buffer of the scene is thus run before the post-processing reading it,
+/// whenever it is added. Passes writing neither an imported texture nor a texture read by another pass are skipped.
+///
+/// Transient textures are allocated from the `TexturePool` of the window when the graph is executed,
+/// textures of the same description whose uses do not overlap sharing the same memory.
+///
+/// Only textures are tracked: the buffers the passes read are all written by the queue before the frame is encoded.
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    textures: Vec<GraphTexture>,
+    imported_views: Vec<&'a TextureView>,
+    passes: Vec<GraphPass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> RenderGraph<'a> {
+        RenderGraph::default()
+    }
+
+    /// Adds a texture living outside of the graph, whose content is kept after the frame.
+    pub fn import_texture(&mut self, view: &'a TextureView) -> TextureId {
+        self.textures.push(GraphTexture::Imported(self.imported_views.len()));
+        self.imported_views.push(view);
+        TextureId(self.textures.len() - 1)
+    }
+
+    /// Adds a texture used during the frame only, whose content is undefined before its first pass.
+    pub fn create_texture(&mut self, description: TransientTexture) -> TextureId {
+        self.textures.push(GraphTexture::Transient(description));
+        TextureId(self.textures.len() - 1)
+    }
+
+    /// Adds a pass, `execute` recording its commands with the views of `reads` and `writes`.
+    /// Textures bound by the pass must be in `reads`, and its attachments in `writes`.
+    pub fn add_pass(&mut self, label: &str, reads: &[TextureId], writes: &[TextureId], execute: impl FnOnce(&mut CommandEncoder, &PassTextures) + 'a) {
+        self.passes.push(GraphPass {
+            label: label.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            execute: Box::new(execute),
+        });
+    }
+
+    /// Orders the passes, allocates the transient textures from `pool` and records the passes into `encoder`.
+    /// Panics if the passes depend on each other in a cycle.
+    pub fn execute(self, encoder: &mut CommandEncoder, pool: &mut TexturePool, device: &Device) {
+        let order = self.order();
+        let lifetimes = self.lifetimes(&order);
+        let allocations = pool.allocate(&self.textures, &lifetimes, device);
+
+        let views: Vec<Option<&TextureView>> = self.textures.iter().zip(allocations.iter()).map(|(texture, allocation)| match texture {
+            GraphTexture::Imported(index) => Some(self.imported_views[*index]),
+            GraphTexture::Transient(_) => allocation.map(|index| &pool.textures[index].1.view),
+        }).collect();
+
+        let mut passes: Vec<Option<GraphPass>> = self.passes.into_iter().map(Some).collect();
+        for pass in order {
+            let GraphPass { label, reads, writes, execute } = passes[pass].take().unwrap();
+            let declared = reads.into_iter().chain(writes).collect();
+            encoder.push_debug_group(&label);
+            execute(encoder, &PassTextures { views: &views, label: &label, declared });
+            encoder.pop_debug_group();
+        }
+    }
+
+    /// First and last position in `order` of each texture, None for the textures no pass of `order` uses.
+    fn lifetimes(&self, order: &[usize]) -> Vec<Option<(usize, usize)>> {
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.textures.len()];
+        for (position, &pass) in order.iter().enumerate() {
+            for texture in self.passes[pass].reads.iter().chain(self.passes[pass].writes.iter()) {
+                let lifetime = lifetimes[texture.0].get_or_insert((position, position));
+                lifetime.1 = position;
+            }
+        }
+        lifetimes
+    }
+
+    /// Indices of the passes to run, in order.
+    fn order(&self) -> Vec<usize> {
+        // dependencies[i]: the passes running before the pass i
+        let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        for texture in 0..self.textures.len() {
+            let texture = TextureId(texture);
+            let writers: Vec<usize> = (0..self.passes.len()).filter(|&i| self.passes[i].writes.contains(&texture)).collect();
+            for pair in writers.windows(2) {
+                dependencies[pair[1]].push(pair[0]);
+            }
+            if let Some(&last_writer) = writers.last() {
+                for (i, pass) in self.passes.iter().enumerate() {
+                    if pass.reads.contains(&texture) && !pass.writes.contains(&texture) {
+                        dependencies[i].push(last_writer);
+                    }
+                }
+            }
+        }
+
+        // passes leading to an imported texture
+        let mut used = vec![false; self.passes.len()];
+        let mut stack: Vec<usize> = (0..self.passes.len())
+            .filter(|&i| self.passes[i].writes.iter().any(|texture| matches!(self.textures[texture.0], GraphTexture::Imported(_))))
+            .collect();
+        while let Some(pass) = stack.pop() {
+            if !used[pass] {
+                used[pass] = true;
+                stack.extend(dependencies[pass].iter().copied());
+            }
+        }
+
+        // the ready pass added first runs first, keeping the order of declaration where it does not matter
+        let mut order = Vec::new();
+        let mut done = vec![false; self.passes.len()];
+        while order.len() < used.iter().filter(|&&used| used).count() {
+            let ready = (0..self.passes.len())
+                .find(|&i| used[i] && !done[i] && dependencies[i].iter().all(|&dependency| done[dependency]));
+            match ready {
+                Some(pass) => {
+                    done[pass] = true;
+                    order.push(pass);
+                }
+                None => {
+                    let cycle: Vec<&str> = (0..self.passes.len()).filter(|&i| used[i] && !done[i]).map(|i| self.passes[i].label.as_str()).collect();
+                    panic!("The passes {:?} of the render graph depend on each other", cycle);
+                }
+            }
+        }
+        order
+    }
+}
+
+/// The transient textures of render graphs, owned by each window of the engine and kept from one frame to the next.
+/// Textures not used by a frame are released, such as the ones of the previous size of the window.
+#[derive(Default)]
+pub struct TexturePool {
+    textures: Vec<(TransientTexture, RenderTarget)>,
+}
+
+impl TexturePool {
+
+    /// Assigns a texture of the pool to each transient texture used between the positions of its lifetime,
+    /// creating the missing ones and releasing the ones no texture uses. Returns the index in the pool of each texture.
+    fn allocate(&mut self, textures: &[GraphTexture], lifetimes: &[Option<(usize, usize)>], device: &Device) -> Vec<Option<usize>> {
+        let descriptions: Vec<TransientTexture> = self.textures.iter().map(|(description, _)| *description).collect();
+        let (allocations, pool) = assign_pool_textures(&descriptions, textures, lifetimes);
+
+        let mut previous: Vec<Option<(TransientTexture, RenderTarget)>> = self.textures.drain(..).map(Some).collect();
+        self.textures = pool.into_iter().map(|(description, kept)| match kept {
+            Some(index) => previous[index].take().unwrap(),
+            None => (description, RenderTarget::new(device, description.width, description.height, description.format, 1, "Transient Texture")),
+        }).collect();
+        allocations
+    }
+}
+
+/// Assigns the transient textures to the textures of a pool made of `pool`, reusing the textures whose previous user
+/// is done. Returns the index in the new pool of each texture, and the new pool: the description of each of its textures
+/// with the index in `pool` of the texture it keeps, None for a texture to create.
+#[allow(clippy::type_complexity)]
+fn assign_pool_textures(pool: &[TransientTexture], textures: &[GraphTexture], lifetimes: &[Option<(usize, usize)>])
+                        -> (Vec<Option<usize>>, Vec<(TransientTexture, Option<usize>)>) {
+    let mut allocations = vec![None; textures.len()];
+    let mut slots: Vec<(TransientTexture, Option<usize>)> = pool.iter().enumerate().map(|(i, description)| (*description, Some(i))).collect();
+    // last position at which each texture of the pool is in use this frame
+    let mut busy_until: Vec<Option<usize>> = vec![None; slots.len()];
+
+    let mut transients: Vec<(usize, TransientTexture, (usize, usize))> = textures.iter().enumerate()
+        .filter_map(|(i, texture)| match (texture, lifetimes[i]) {
+            (GraphTexture::Transient(description), Some(lifetime)) => Some((i, *description, lifetime)),
+            _ => None,
+        })
+        .collect();
+    transients.sort_by_key(|(_, _, (first, _))| *first);
+
+    for (texture, description, (first, last)) in transients {
+        let free = (0..slots.len())
+            .find(|&i| slots[i].0 == description && busy_until[i].is_none_or(|until| until < first));
+        let index = free.unwrap_or_else(|| {
+            slots.push((description, None));
+            busy_until.push(None);
+            slots.len() - 1
+        });
+        busy_until[index] = Some(last);
+        allocations[texture] = Some(index);
+    }
+
+    // release the unused textures, shifting the indices of the allocations
+    let mut new_indices = vec![None; slots.len()];
+    let mut kept = Vec::new();
+    for (i, slot) in slots.into_iter().enumerate() {
+        if busy_until[i].is_some() {
+            new_indices[i] = Some(kept.len());
+            kept.push(slot);
+        }
+    }
+    let allocations = allocations.iter().map(|allocation| allocation.and_then(|index| new_indices[index])).collect();
+    (allocations, kept)
+}
+
+/// Passes added by the application to the render graph of every camera, see `Scene::custom_render_passes`.
+pub trait CustomRenderPass {
+    /// Adds passes to `graph` for the camera whose textures are `view`, with `scene_bind_group` as group 0.
+    fn add_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, view: &ViewTextures, scene_bind_group: &'a BindGroup, engine: &'a Engine);
+}
+
+/// The textures of a camera in the render graph of a frame.
+#[derive(Debug, Copy, Clone)]
+pub struct ViewTextures {
+    /// color of the scene before post-processing, in `HDR_FORMAT`
+    pub hdr: TextureId,
+    /// single sampled depth of the scene, in `DEPTH_FORMAT`
+    pub depth: TextureId,
+    /// the surface or the render texture the post-processing draws into
+    pub output: TextureId,
+    pub output_format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assign_pool_textures, GraphTexture, RenderGraph, TextureId, TransientTexture};
+
+    const HDR: TransientTexture = TransientTexture { width: 64, height: 64, format: wgpu::TextureFormat::Rgba16Float };
+    const HALF: TransientTexture = TransientTexture { width: 32, height: 32, format: wgpu::TextureFormat::Rgba16Float };
+
+    /// An imported texture without view, the tests never executing the graph.
+    fn import(graph: &mut RenderGraph) -> TextureId {
+        graph.textures.push(GraphTexture::Imported(0));
+        TextureId(graph.textures.len() - 1)
+    }
+
+    fn add_pass(graph: &mut RenderGraph, reads: &[TextureId], writes: &[TextureId]) {
+        let label = graph.passes.len().to_string();
+        graph.add_pass(&label, reads, writes, |_, _| {});
+    }
+
+    #[test]
+    fn writers_run_in_declaration_order() {
+        let mut graph = RenderGraph::new();
+        let (hdr, output) = (import(&mut graph), import(&mut graph));
+        add_pass(&mut graph, &[hdr], &[output]);
+        add_pass(&mut graph, &[], &[hdr]);
+        add_pass(&mut graph, &[hdr], &[hdr]);
+        add_pass(&mut graph, &[], &[hdr]);
+
+        assert_eq!(graph.order(), vec![1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn readers_run_after_the_last_writer() {
+        let mut graph = RenderGraph::new();
+        let (output, overlay) = (import(&mut graph), import(&mut graph));
+        let color = graph.create_texture(HDR);
+        // a post-process added before the pass drawing over the color it reads
+        add_pass(&mut graph, &[color], &[output]);
+        add_pass(&mut graph, &[], &[color]);
+        add_pass(&mut graph, &[color], &[overlay]);
+        add_pass(&mut graph, &[color], &[color]);
+
+        let order = graph.order();
+        let position = |pass: usize| order.iter().position(|&i| i == pass).unwrap();
+        assert!(position(1) < position(3));
+        assert!(position(3) < position(0));
+        assert!(position(3) < position(2));
+    }
+
+    #[test]
+    fn passes_not_leading_to_imported_textures_are_culled() {
+        let mut graph = RenderGraph::new();
+        let output = import(&mut graph);
+        let (used, unused) = (graph.create_texture(HDR), graph.create_texture(HDR));
+        add_pass(&mut graph, &[], &[used]);
+        add_pass(&mut graph, &[used], &[unused]);
+        add_pass(&mut graph, &[used], &[output]);
+
+        let order = graph.order();
+        assert_eq!(order, vec![0, 2]);
+        let lifetimes = graph.lifetimes(&order);
+        assert_eq!(lifetimes[used.0], Some((0, 1)));
+        assert_eq!(lifetimes[unused.0], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "depend on each other")]
+    fn cycles_panic() {
+        let mut graph = RenderGraph::new();
+        let output = import(&mut graph);
+        let (a, b) = (graph.create_texture(HDR), graph.create_texture(HDR));
+        add_pass(&mut graph, &[a], &[b, output]);
+        add_pass(&mut graph, &[b], &[a]);
+
+        graph.order();
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_pool_textures() {
+        let textures = [GraphTexture::Transient(HDR), GraphTexture::Transient(HDR), GraphTexture::Transient(HDR), GraphTexture::Transient(HALF)];
+        let lifetimes = [Some((0, 1)), Some((2, 3)), Some((1, 2)), Some((0, 3))];
+
+        let (allocations, pool) = assign_pool_textures(&[], &textures, &lifetimes);
+        assert_eq!(allocations[0], allocations[1]);
+        assert_ne!(allocations[0], allocations[2]);
+        assert_ne!(allocations[3], allocations[0]);
+        assert_ne!(allocations[3], allocations[2]);
+        assert_eq!(pool.len(), 3);
+        assert!(pool.iter().all(|(_, kept)| kept.is_none()));
+    }
+
+    #[test]
+    fn pool_textures_are_kept_while_used() {
+        let textures = [GraphTexture::Imported(0), GraphTexture::Transient(HALF), GraphTexture::Transient(HALF)];
+        let lifetimes = [Some((0, 2)), Some((0, 0)), None];
+
+        // the previous frame used a texture of another size then one of the same size
+        let (allocations, pool) = assign_pool_textures(&[HDR, HALF], &textures, &lifetimes);
+        assert_eq!(allocations, vec![None, Some(0), None]);
+        assert_eq!(pool, vec![(HALF, Some(1))]);
+    }
+}
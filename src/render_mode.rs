@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use wgpu::{PipelineLayout, RenderPipeline};
+use crate::engine::Engine;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::vertex::{VertexAttribute, VertexBufferLayouts, BASE_ATTRIBUTES};
+use crate::shader::ShaderPreprocessor;
+
+/// How meshes are drawn. Every mode but `Solid` replaces or overlays the material of the mesh with a debug pipeline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RenderMode {
+    Solid,
+    Wireframe,
+    WireframeOverSolid,
+    /// world space normals as colors
+    Normals,
+    /// object space height relative to the bounding box of the mesh
+    Height,
+    /// steepness relative to the world up axis
+    Slope,
+    /// 16x16 checker of the texture coordinates, or of the object space position scaled to the bounding box
+    /// of the mesh for meshes without uvs
+    Checker,
+    /// linear distance to the camera, white when close
+    Depth,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 8] = [
+        RenderMode::Solid,
+        RenderMode::Wireframe,
+        RenderMode::WireframeOverSolid,
+        RenderMode::Normals,
+        RenderMode::Height,
+        RenderMode::Slope,
+        RenderMode::Checker,
+        RenderMode::Depth,
+    ];
+
+    /// The mode following this one, used to cycle through modes with a key.
+    pub fn next(self) -> RenderMode {
+        let index = RenderMode::ALL.iter().position(|mode| *mode == self).unwrap();
+        RenderMode::ALL[(index + 1) % RenderMode::ALL.len()]
+    }
+
+    /// Whether the material of the mesh is drawn in this mode.
+    pub fn draws_material(self) -> bool {
+        matches!(self, RenderMode::Solid | RenderMode::WireframeOverSolid)
+    }
+
+    /// Whether the pipeline of this mode reads the uvs of the meshes providing them.
+    fn reads_uv(self) -> bool {
+        self == RenderMode::Checker
+    }
+
+    fn shader_flag(self) -> Option<&'static str> {
+        match self {
+            RenderMode::Solid => None,
+            RenderMode::Wireframe => Some("RENDER_MODE_WIREFRAME"),
+            RenderMode::WireframeOverSolid => Some("RENDER_MODE_WIREFRAME_OVERLAY"),
+            RenderMode::Normals => Some("RENDER_MODE_NORMALS"),
+            RenderMode::Height => Some("RENDER_MODE_HEIGHT"),
+            RenderMode::Slope => Some("RENDER_MODE_SLOPE"),
+            RenderMode::Checker => Some("RENDER_MODE_CHECKER"),
+            RenderMode::Depth => Some("RENDER_MODE_DEPTH"),
+        }
+    }
+
+    fn pipeline_settings(self) -> PipelineSettings {
+        match self {
+            RenderMode::Wireframe => PipelineSettings {
+                polygon_mode: wgpu::PolygonMode::Line,
+                ..Default::default()
+            },
+            // drawn on top of the material, pulled towards the camera to win the depth test
+            RenderMode::WireframeOverSolid => PipelineSettings {
+                polygon_mode: wgpu::PolygonMode::Line,
+                depth_write_enabled: false,
+                depth_bias: wgpu::DepthBiasState {
+                    constant: -2,
+                    slope_scale: -1.0,
+                    clamp: 0.0,
+                },
+                ..Default::default()
+            },
+            _ => PipelineSettings::default(),
+        }
+    }
+}
+
+/// attributes read by the debug pipelines of modes reading uvs, for the meshes providing them
+const UV_ATTRIBUTES: [VertexAttribute; 4] = [VertexAttribute::Position, VertexAttribute::Color(0), VertexAttribute::Normal, VertexAttribute::Uv];
+
+/// Pipelines of the debug render modes, created on demand from `debug.wgsl`.
+/// Modes reading uvs have a second pipeline for the meshes providing them, see `RenderMode::Checker`.
+pub struct DebugPipelines {
+    pipeline_layout: PipelineLayout,
+    /// pipelines by mode and whether they read uvs
+    pipelines: HashMap<(RenderMode, bool), RenderPipeline>,
+    /// variants whose pipeline failed to compile, not retried until `clear` is called
+    failed: HashSet<(RenderMode, bool)>,
+}
+
+impl DebugPipelines {
+    pub fn new(engine: &Engine) -> DebugPipelines {
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &engine.layouts.mesh],
+            push_constant_ranges: &[],
+        });
+
+        DebugPipelines {
+            pipeline_layout,
+            pipelines: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    /// Creates the pipeline of `mode` for meshes with or without uvs if it does not exist yet.
+    /// On failure the error is printed and the mode falls back to drawing materials only.
+    pub fn prepare(&mut self, mode: RenderMode, has_uv: bool, engine: &Engine) {
+        let flag = match mode.shader_flag() {
+            Some(flag) => flag,
+            None => return,
+        };
+        let key = (mode, has_uv && mode.reads_uv());
+        if self.pipelines.contains_key(&key) || self.failed.contains(&key) { return; }
+
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.define(flag, "");
+        if key.1 {
+            preprocessor.define("HAS_UV", "");
+        }
+        preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+
+        let layouts = VertexBufferLayouts::new(attributes(key.1));
+        match create_pipeline("debug", &preprocessor, &self.pipeline_layout, &layouts.desc(), &mode.pipeline_settings(), engine) {
+            Ok((_, pipeline)) => { self.pipelines.insert(key, pipeline); }
+            Err(e) => {
+                eprintln!("Could not create the pipeline of render mode {:?}: {}", mode, e);
+                self.failed.insert(key);
+            }
+        }
+    }
+
+    /// The pipeline of `mode` for meshes with or without uvs, along with the vertex attributes it reads.
+    pub fn get(&self, mode: RenderMode, has_uv: bool) -> Option<(&RenderPipeline, &'static [VertexAttribute])> {
+        let key = (mode, has_uv && mode.reads_uv());
+        self.pipelines.get(&key).map(|pipeline| (pipeline, attributes(key.1)))
+    }
+
+    /// Drops every pipeline so they are recompiled the next time they are prepared.
+    pub fn clear(&mut self) {
+        self.pipelines.clear();
+        self.failed.clear();
+    }
+}
+
+fn attributes(uv: bool) -> &'static [VertexAttribute] {
+    if uv { &UV_ATTRIBUTES } else { &BASE_ATTRIBUTES }
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use wgpu::{BindGroup, Device, Texture, TextureView};
+use crate::post_process::HDR_FORMAT;
+use crate::uniforms::BindGroupLayouts;
+
+/// Format of the depth buffers of the main pass.
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+
+/// Format of the occlusion texture of the ambient occlusion pass, allocated by the render graph.
+pub const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Maximum number of half size textures the bloom is blurred through, fewer for small windows, allocated by the render graph.
+pub const BLOOM_LEVELS: usize = 6;
+
+/// A texture drawn into by a pass and usually read by the following ones.
+pub struct RenderTarget {
+    pub texture: Texture,
+    pub view: TextureView,
+}
+
+impl RenderTarget {
+    pub fn new(device: &Device, width: u32, height: u32, format: wgpu::TextureFormat, sample_count: u32, label: &str) -> RenderTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // read by the passes drawn after the one writing it
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some(label),
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        RenderTarget { texture, view }
+    }
+}
+
+/// The buffers the scene is rendered into, owned by each window of the engine and recreated only when the window is resized,
+/// see `Engine::resize`. They have `Engine::sample_count` samples per pixel.
+/// The textures used by the post-processing only are transient textures of the render graph.
+pub struct RenderTargets {
+    /// unique among the targets created, a new one after each resize, so the bind groups reading them can be cached
+    pub id: u64,
+    pub width: u32,
+    pub height: u32,
+    pub sample_count: u32,
+    /// depth of the main pass, read by the fog, the atmosphere, the water and the post-processing
+    pub depth: RenderTarget,
+    /// color of the scene before post-processing
+    pub hdr: RenderTarget,
+    /// color and depth of the main pass with multisampling, resolved into `hdr` and `depth`
+    pub multisampled: Option<(RenderTarget, RenderTarget)>,
+    /// group 3 of materials reading `depth`, see `Material::reads_scene_depth`
+    pub scene_depth_bind_group: BindGroup,
+}
+
+impl RenderTargets {
+    pub fn new(device: &Device, layouts: &BindGroupLayouts, width: u32, height: u32, sample_count: u32) -> RenderTargets {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let multisampled = (sample_count > 1).then(|| (
+            RenderTarget::new(device, width, height, HDR_FORMAT, sample_count, "Multisampled HDR Texture"),
+            RenderTarget::new(device, width, height, DEPTH_FORMAT, sample_count, "Multisampled Depth Texture"),
+        ));
+
+        let depth = RenderTarget::new(device, width, height, DEPTH_FORMAT, 1, "Depth Texture");
+        let scene_depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.scene_depth,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            }],
+            label: Some("Scene Depth Bind Group"),
+        });
+
+        RenderTargets {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            width,
+            height,
+            sample_count,
+            depth,
+            hdr: RenderTarget::new(device, width, height, HDR_FORMAT, 1, "HDR Texture"),
+            multisampled,
+            scene_depth_bind_group,
+        }
+    }
+
+    /// Recreates the targets if their size or sample count changed.
+    pub fn resize(&mut self, device: &Device, layouts: &BindGroupLayouts, width: u32, height: u32, sample_count: u32) {
+        if (width, height, sample_count) != (self.width, self.height, self.sample_count) {
+            *self = RenderTargets::new(device, layouts, width, height, sample_count);
+        }
+    }
+}
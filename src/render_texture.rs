@@ -0,0 +1,63 @@
+use std::rc::Rc;
+use bytemuck::{cast_slice, Zeroable};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer};
+use crate::camera::BasicCamera;
+use crate::engine::Engine;
+use crate::render_target::RenderTargets;
+use crate::texture::Texture;
+use crate::uniforms::CameraUniforms;
+
+/// Format of the textures of render textures, holding post-processed colors.
+pub const RENDER_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A camera rendering the scene into a texture every frame, before the scene is drawn on screen,
+/// for minimaps, reflections or picture-in-picture views. See `Scene::add_render_texture`.
+/// The texture is post-processed like the screen but without debug drawings, and is given to materials like any other.
+/// A mesh whose material samples the texture cannot be drawn into it, so it must be kept out of `layers`.
+/// The shadow maps of the sun are fitted to the active camera of the scene, so far from it meshes are not shadowed.
+pub struct RenderTexture {
+    pub camera: BasicCamera,
+    /// bit mask of the layers of the meshes drawn, see `Mesh::layers`
+    pub layers: u32,
+    /// whether the texture is rendered every frame, keeping its last image otherwise
+    pub enabled: bool,
+    texture: Rc<Texture>,
+    /// intermediate buffers of the passes, at the size of the texture
+    pub(crate) targets: RenderTargets,
+    pub(crate) camera_uniform_buffer: Buffer,
+    /// group 0 seeing the scene through `camera`, created by `Scene::update` and dropped when the scene bind groups change
+    pub(crate) scene_bind_group: Option<BindGroup>,
+}
+
+impl RenderTexture {
+    /// Creates a `width` x `height` texture seen through a camera of the same aspect ratio, drawing every layer.
+    pub fn new(width: u32, height: u32, engine: &Engine) -> RenderTexture {
+        let camera_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Render Texture Camera Uniform Buffer"),
+            contents: cast_slice(&[CameraUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        RenderTexture {
+            camera: BasicCamera::new(width as f32 / height as f32),
+            layers: u32::MAX,
+            enabled: true,
+            texture: Rc::new(Texture::new_render_attachment(width, height, RENDER_TEXTURE_FORMAT, "Render Texture", engine)),
+            targets: RenderTargets::new(&engine.device, &engine.layouts, width, height, engine.sample_count()),
+            camera_uniform_buffer,
+            scene_bind_group: None,
+        }
+    }
+
+    /// The texture the scene is rendered into, to be shared with materials.
+    pub fn texture(&self) -> &Rc<Texture> {
+        &self.texture
+    }
+
+    /// Uploads the camera.
+    pub(crate) fn update(&mut self, time: f32, engine: &Engine) {
+        let camera_uniforms = CameraUniforms::from_camera(&self.camera, time);
+        engine.queue.write_buffer(&self.camera_uniform_buffer, 0, cast_slice(&[camera_uniforms]));
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use cgmath::{InnerSpace, Matrix3, Point3, Quaternion, Rad, Vector3};
+use crate::instancing::Instance;
+use crate::transform::Transform;
+
+/// A procedural terrain to scatter instances on, described by the same parameters as the mesh it was made with.
+/// Heights and normals are sampled from the height function, so instances may float slightly above or sink below
+/// the triangles of coarse terrains, see `ScatterOptions::offset`.
+pub enum TerrainSurface<'a> {
+    /// a terrain made by `Mesh::new_procedural_terrain` with the same `size`, `nb_subdivisions` and `height_fn`
+    Flat { size: f32, nb_subdivisions: u32, height_fn: &'a dyn Fn(f32, f32) -> f32 },
+    /// a planet made by `Mesh::new_procedural_sphere` with the same `diameter` and `height_fn`
+    Sphere { diameter: f32, height_fn: &'a dyn Fn(f32, f32, f32) -> f32 },
+}
+
+/// A point of a terrain surface, in the space of the terrain mesh.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceSample {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    /// direction of the normal of the terrain before its heights were applied: the y axis for flat terrains,
+    /// away from the center for spheres
+    pub up: Vector3<f32>,
+    /// height above the plane or the sphere the terrain was made from
+    pub height: f32,
+}
+
+impl SurfaceSample {
+    /// Angle in radians between the normal and `up`, 0 on flat ground.
+    pub fn slope(&self) -> f32 {
+        self.normal.dot(self.up).clamp(-1.0, 1.0).acos()
+    }
+}
+
+impl TerrainSurface<'_> {
+    /// Samples the terrain above `point`, a point of the plane y = 0 for flat terrains or a direction for spheres.
+    pub fn sample(&self, point: Vector3<f32>) -> SurfaceSample {
+        match self {
+            TerrainSurface::Flat { size, height_fn, .. } => {
+                let epsilon = size * 1e-4;
+                let height = height_fn(point.x, point.z);
+                let dh_dx = (height_fn(point.x + epsilon, point.z) - height_fn(point.x - epsilon, point.z)) / (2.0 * epsilon);
+                let dh_dz = (height_fn(point.x, point.z + epsilon) - height_fn(point.x, point.z - epsilon)) / (2.0 * epsilon);
+
+                SurfaceSample {
+                    position: Vector3::new(point.x, height, point.z),
+                    normal: Vector3::new(-dh_dx, 1.0, -dh_dz).normalize(),
+                    up: Vector3::unit_y(),
+                    height,
+                }
+            }
+            TerrainSurface::Sphere { diameter, height_fn } => {
+                let radius = diameter / 2.0;
+                let surface_point = |direction: Vector3<f32>| {
+                    let direction = direction.normalize();
+                    direction * (radius + height_fn(direction.x, direction.y, direction.z))
+                };
+
+                let up = point.normalize();
+                let (tangent, bitangent) = tangent_basis(up);
+                let epsilon = 1e-4;
+                let along_tangent = surface_point(up + tangent * epsilon) - surface_point(up - tangent * epsilon);
+                let along_bitangent = surface_point(up + bitangent * epsilon) - surface_point(up - bitangent * epsilon);
+                let normal = along_bitangent.cross(along_tangent).normalize();
+                let height = height_fn(up.x, up.y, up.z);
+
+                SurfaceSample {
+                    position: up * (radius + height),
+                    normal: if normal.dot(up) < 0.0 { -normal } else { normal },
+                    up,
+                    height,
+                }
+            }
+        }
+    }
+
+    /// Projects `point` on the plane or the sphere the terrain was made from, None outside of the terrain.
+    fn base_point(&self, point: Vector3<f32>) -> Option<Vector3<f32>> {
+        match self {
+            TerrainSurface::Flat { size, nb_subdivisions, .. } => {
+                // same extent as the vertices of `new_procedural_terrain`
+                let min = -size / 2.0;
+                let max = (*nb_subdivisions as f32 / 2.0 - 1.0) * size / *nb_subdivisions as f32;
+                let inside = (min..=max).contains(&point.x) && (min..=max).contains(&point.z);
+                inside.then(|| Vector3::new(point.x, 0.0, point.z))
+            }
+            TerrainSurface::Sphere { diameter, .. } => Some(point.normalize() * *diameter / 2.0),
+        }
+    }
+
+    fn random_base_point(&self, random: &mut Random) -> Vector3<f32> {
+        match self {
+            TerrainSurface::Flat { size, nb_subdivisions, .. } => {
+                let min = -size / 2.0;
+                let extent = (*nb_subdivisions - 1) as f32 * size / *nb_subdivisions as f32;
+                Vector3::new(min + random.next() * extent, 0.0, min + random.next() * extent)
+            }
+            TerrainSurface::Sphere { diameter, .. } => {
+                // uniform on the sphere
+                let y = random.next() * 2.0 - 1.0;
+                let angle = random.next() * std::f32::consts::TAU;
+                let r = (1.0 - y * y).sqrt();
+                Vector3::new(r * angle.cos(), y, r * angle.sin()) * *diameter / 2.0
+            }
+        }
+    }
+
+    fn base_up(&self, point: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            TerrainSurface::Flat { .. } => Vector3::unit_y(),
+            TerrainSurface::Sphere { .. } => point.normalize(),
+        }
+    }
+}
+
+/// Rules placing instances with `scatter`. Lengths are in the space of the terrain mesh and angles in radians.
+pub struct ScatterOptions<'a> {
+    /// minimum distance between two instances, measured on the plane or the sphere the terrain was made from
+    pub min_distance: f32,
+    /// range of heights of the terrain the instances are placed at, see `SurfaceSample::height`
+    pub min_height: f32,
+    pub max_height: f32,
+    /// range of slopes of the terrain the instances are placed on, see `SurfaceSample::slope`
+    pub min_slope: f32,
+    pub max_slope: f32,
+    /// probability in [0, 1] to keep an instance at a position of the terrain, such as a painted mask or a noise
+    pub density: Option<&'a dyn Fn(Vector3<f32>) -> f32>,
+    /// the same seed and options always place the same instances
+    pub seed: u64,
+    /// 0 keeps the instances upright, 1 aligns their y axis with the normal of the terrain
+    pub align_to_normal: f32,
+    /// rotates each instance by a random angle around its y axis
+    pub random_rotation: bool,
+    /// range of the uniform scaling of the instances
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// darkens the color of each instance by a random fraction up to this value
+    pub color_variation: f32,
+    /// moves the instances along their y axis, negative values sinking their base into the ground
+    pub offset: f32,
+}
+
+impl Default for ScatterOptions<'_> {
+    fn default() -> Self {
+        ScatterOptions {
+            min_distance: 1.0,
+            min_height: f32::NEG_INFINITY,
+            max_height: f32::INFINITY,
+            min_slope: 0.0,
+            max_slope: std::f32::consts::FRAC_PI_2,
+            density: None,
+            seed: 0,
+            align_to_normal: 0.0,
+            random_rotation: true,
+            min_scale: 1.0,
+            max_scale: 1.0,
+            color_variation: 0.0,
+            offset: 0.0,
+        }
+    }
+}
+
+/// Number of candidates tried around each point before giving up on it.
+const POISSON_CANDIDATES: u32 = 30;
+
+/// Places instances on `surface` following `options`, to be drawn by an `InstancedMesh` sharing the transform of the terrain.
+/// Positions are distributed with a Poisson disk distribution, then filtered by the height, slope and density rules.
+pub fn scatter(surface: &TerrainSurface, options: &ScatterOptions) -> Vec<Instance> {
+    assert!(options.min_distance > 0.0, "The minimum distance between instances must be positive");
+    let mut random = Random::new(options.seed);
+
+    poisson_disk(surface, options.min_distance, &mut random).into_iter().filter_map(|point| {
+        let sample = surface.sample(point);
+        // drawn for every point so the rules do not change the placement of the other instances
+        let (keep, yaw, scale, shade) = (random.next(), random.next(), random.next(), random.next());
+
+        let slope = sample.slope();
+        let density = options.density.map_or(1.0, |density| density(sample.position));
+        if sample.height < options.min_height || sample.height > options.max_height
+            || slope < options.min_slope || slope > options.max_slope || keep >= density {
+            return None;
+        }
+
+        let up = (sample.up * (1.0 - options.align_to_normal) + sample.normal * options.align_to_normal).normalize();
+        let yaw = if options.random_rotation { yaw * std::f32::consts::TAU } else { 0.0 };
+        let rotation = Matrix3::from(Quaternion::from_arc(Vector3::unit_y(), up, None)) * Matrix3::from_angle_y(Rad(yaw));
+        let scale = options.min_scale + scale * (options.max_scale - options.min_scale);
+        let shade = 1.0 - shade * options.color_variation;
+
+        Some(Instance {
+            transform: Transform {
+                position: sample.position + up * options.offset,
+                rotation: euler_angles(rotation),
+                scaling: Point3::new(scale, scale, scale),
+            },
+            color: [shade, shade, shade],
+        })
+    }).collect()
+}
+
+/// Bridson's algorithm: points at least `min_distance` apart covering the plane or the sphere of `surface`.
+fn poisson_disk(surface: &TerrainSurface, min_distance: f32, random: &mut Random) -> Vec<Vector3<f32>> {
+    let cell = |point: Vector3<f32>| {
+        let cell = point / min_distance;
+        (cell.x.floor() as i32, cell.y.floor() as i32, cell.z.floor() as i32)
+    };
+    let mut grid: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+    let is_free = |grid: &HashMap<(i32, i32, i32), Vec<usize>>, points: &[Vector3<f32>], point: Vector3<f32>| {
+        let (x, y, z) = cell(point);
+        (-1..=1).all(|i| (-1..=1).all(|j| (-1..=1).all(|k| {
+            grid.get(&(x + i, y + j, z + k)).is_none_or(|neighbors| {
+                neighbors.iter().all(|&neighbor| (points[neighbor] - point).magnitude2() >= min_distance * min_distance)
+            })
+        })))
+    };
+
+    let first = surface.random_base_point(random);
+    let mut points = vec![first];
+    let mut active = vec![0];
+    grid.entry(cell(first)).or_default().push(0);
+
+    while !active.is_empty() {
+        let active_index = (random.next() * active.len() as f32) as usize % active.len();
+        let center = points[active[active_index]];
+        let (tangent, bitangent) = tangent_basis(surface.base_up(center));
+
+        let mut found = false;
+        for _ in 0..POISSON_CANDIDATES {
+            let angle = random.next() * std::f32::consts::TAU;
+            let distance = min_distance * (1.0 + random.next());
+            let candidate = center + (tangent * angle.cos() + bitangent * angle.sin()) * distance;
+
+            if let Some(candidate) = surface.base_point(candidate) {
+                if is_free(&grid, &points, candidate) {
+                    grid.entry(cell(candidate)).or_default().push(points.len());
+                    active.push(points.len());
+                    points.push(candidate);
+                    found = true;
+                    break;
+                }
+            }
+        }
+
+        if !found {
+            active.swap_remove(active_index);
+        }
+    }
+
+    points
+}
+
+/// Two unit vectors orthogonal to `normal` and to each other.
+fn tangent_basis(normal: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let reference = if normal.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let tangent = reference.cross(normal).normalize();
+    (tangent, normal.cross(tangent))
+}
+
+/// Angles of `Transform::rotation` giving `rotation`, which is applied as rotations around z, y then x.
+fn euler_angles(rotation: Matrix3<f32>) -> Point3<f32> {
+    // cgmath matrices are indexed by column then row
+    let sin_y = -rotation.x.z.clamp(-1.0, 1.0);
+    if sin_y.abs() > 0.9999 {
+        // gimbal lock, the rotation around z can be folded into the one around x
+        Point3::new((-rotation.z.y).atan2(rotation.y.y), sin_y.asin(), 0.0)
+    } else {
+        Point3::new(rotation.y.z.atan2(rotation.z.z), sin_y.asin(), rotation.x.y.atan2(rotation.x.x))
+    }
+}
+
+/// SplitMix64, a small deterministic generator so the same seed places the same instances on every platform.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    /// Uniform in [0, 1).
+    fn next(&mut self) -> f32 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3};
+    use super::{scatter, ScatterOptions, TerrainSurface};
+
+    const SIZE: f32 = 20.0;
+    const NB_SUBDIVISIONS: u32 = 32;
+
+    fn flat(height_fn: &dyn Fn(f32, f32) -> f32) -> TerrainSurface<'_> {
+        TerrainSurface::Flat { size: SIZE, nb_subdivisions: NB_SUBDIVISIONS, height_fn }
+    }
+
+    fn positions(surface: &TerrainSurface, options: &ScatterOptions) -> Vec<Vector3<f32>> {
+        scatter(surface, options).into_iter().map(|instance| instance.transform.position).collect()
+    }
+
+    fn hills(x: f32, z: f32) -> f32 {
+        (x * 0.5).sin() * (z * 0.3).cos() * 2.0
+    }
+
+    #[test]
+    fn same_seed_places_same_instances() {
+        let surface = flat(&hills);
+        let options = |seed| ScatterOptions { seed, min_scale: 0.5, color_variation: 0.5, ..Default::default() };
+
+        let first = scatter(&surface, &options(7));
+        let second = scatter(&surface, &options(7));
+        assert!(!first.is_empty());
+        assert_eq!(first.len(), second.len());
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.transform.position, b.transform.position);
+            assert_eq!(a.transform.rotation, b.transform.rotation);
+            assert_eq!(a.transform.scaling, b.transform.scaling);
+            assert_eq!(a.color, b.color);
+        }
+        assert_ne!(positions(&surface, &options(7)), positions(&surface, &options(8)));
+    }
+
+    #[test]
+    fn instances_respect_min_distance_on_flat_terrains() {
+        let surface = flat(&hills);
+        let options = ScatterOptions { min_distance: 1.5, ..Default::default() };
+        let points = positions(&surface, &options);
+        assert!(points.len() > 20);
+
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                // measured on the plane the terrain was made from
+                let distance = Vector3::new(a.x - b.x, 0.0, a.z - b.z).magnitude();
+                assert!(distance >= options.min_distance * 0.999, "{:?} and {:?} are {} apart", a, b, distance);
+            }
+        }
+    }
+
+    #[test]
+    fn instances_respect_min_distance_on_spheres() {
+        let height_fn = |_: f32, _: f32, _: f32| 0.0;
+        let surface = TerrainSurface::Sphere { diameter: 10.0, height_fn: &height_fn };
+        let options = ScatterOptions { min_distance: 1.0, ..Default::default() };
+        let points = positions(&surface, &options);
+        assert!(points.len() > 20);
+
+        for (i, a) in points.iter().enumerate() {
+            assert!((a.magnitude() - 5.0).abs() < 1e-3);
+            for b in &points[i + 1..] {
+                assert!((a - b).magnitude() >= options.min_distance * 0.999);
+            }
+        }
+    }
+
+    #[test]
+    fn height_filter_keeps_the_other_instances_in_place() {
+        // a ramp rising along x
+        let ramp = |x: f32, _: f32| x;
+        let surface = flat(&ramp);
+        let all = positions(&surface, &ScatterOptions::default());
+        let high = positions(&surface, &ScatterOptions { min_height: 0.0, ..Default::default() });
+
+        assert!(!high.is_empty() && high.len() < all.len());
+        assert!(high.iter().all(|position| position.y >= 0.0));
+        assert_eq!(high, all.into_iter().filter(|position| position.y >= 0.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn slope_filter() {
+        // flat for negative x, at 45 degrees for positive x
+        let step = |x: f32, _: f32| x.max(0.0);
+        let surface = flat(&step);
+        let margin = SIZE * 1e-3;
+
+        let gentle = positions(&surface, &ScatterOptions { max_slope: 0.1, ..Default::default() });
+        assert!(!gentle.is_empty());
+        assert!(gentle.iter().all(|position| position.x <= margin));
+
+        let steep = positions(&surface, &ScatterOptions { min_slope: 0.7, ..Default::default() });
+        assert!(!steep.is_empty());
+        assert!(steep.iter().all(|position| position.x >= -margin));
+    }
+}
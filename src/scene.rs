@@ -1,29 +1,215 @@
+use std::collections::HashMap;
 use std::iter;
-use bytemuck::cast_slice;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::time::Instant;
+use bytemuck::{cast_slice, Zeroable};
 use cgmath::{InnerSpace, Rotation3};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer, PipelineLayout, RenderPass, RenderPipeline, TextureView};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use winit::event::{ElementState, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+use winit::window::WindowId;
 use crate::engine::Engine;
 use crate::camera::{BasicCamera, FreeCamera};
 use crate::mesh::{Mesh};
 use crate::camera::Transformable;
+use crate::debug::DebugRenderer;
+use crate::render_mode::{DebugPipelines, RenderMode};
+use crate::atmosphere::{Atmosphere, AtmosphereRenderer};
+use crate::background::{Background, BackgroundRenderer};
+use crate::fog::{Fog, FogRenderer};
+use crate::instancing::InstancedMesh;
+use crate::material::{create_pipeline, Material, PipelineSettings};
+use crate::msaa::DepthResolver;
+use crate::post_process::PostProcessChain;
+use crate::render_graph::{CustomRenderPass, RenderGraph, TextureId, TransientTexture, ViewTextures};
+use crate::render_target::RenderTargets;
+use crate::render_texture::{RenderTexture, RENDER_TEXTURE_FORMAT};
+use crate::light::{DirectionalLight, HemisphereLight, LightUniforms};
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::shadow::{ShadowMap, ShadowSettings, SHADOW_ATTRIBUTES};
+use crate::texture::{Texture, TextureOptions};
+use crate::uniforms::CameraUniforms;
+use crate::vertex::{VertexAttribute, VertexBufferLayouts};
+use crate::viewport::Viewport;
 
 pub const ANIMATION_SPEED: f32 = 1.0;
 
 pub struct Scene {
     pub active_camera: BasicCamera,
     pub meshes: Vec<Mesh>,
+    /// meshes drawn many times in a single draw call, after the other opaque meshes
+    pub instanced_meshes: Vec<InstancedMesh>,
+    /// bit mask of the layers of the meshes drawn by the active camera, see `Mesh::layers`
+    pub layers: u32,
+    /// cameras rendering the scene into textures before it is drawn on screen
+    pub render_textures: Vec<RenderTexture>,
+    /// regions of the window showing the scene through their own camera, the active camera filling the window when empty
+    pub viewports: Vec<Viewport>,
+    /// passes of the application, added to the render graph of every camera after the built-in ones
+    pub custom_render_passes: Vec<Box<dyn CustomRenderPass>>,
     pub execute_before_render: Box<dyn FnMut()>,
+    /// render mode of every mesh without its own, cycled with the Tab key
+    pub render_mode: RenderMode,
+    pub debug_pipelines: DebugPipelines,
+    pub debug_renderer: DebugRenderer,
+    /// the sun, lighting and later shadowing the scene
+    pub sun: DirectionalLight,
+    pub ambient_light: HemisphereLight,
+    /// scale of the light of the environment map, see `set_environment_map`
+    pub environment_intensity: f32,
+    environment_map: Option<Rc<Texture>>,
+    /// black texture bound when there is no environment map
+    default_environment_map: Texture,
+    shadow_map: ShadowMap,
+    /// sky and aerial perspective, drawn over the meshes
+    pub atmosphere: Option<Atmosphere>,
+    atmosphere_renderer: AtmosphereRenderer,
+    /// distance fog over the meshes, under the atmosphere if any
+    pub fog: Option<Fog>,
+    fog_renderer: FogRenderer,
+    /// groups 1 of the fog and the atmosphere reading the depth buffer of each view, by `RenderTargets::id`
+    depth_effect_bind_groups: HashMap<u64, (BindGroup, BindGroup)>,
+    /// seen behind the meshes and through the atmosphere if any
+    pub background: Background,
+    background_renderer: BackgroundRenderer,
+    /// turns the HDR colors the scene is rendered with into the image on screen
+    pub post_process: PostProcessChain,
+    pub camera_uniform_buffer: Buffer,
+    pub light_uniform_buffer: Buffer,
+    /// group 0 of every pipeline, see `BindGroupLayouts::scene`
+    pub scene_bind_group: BindGroup,
+    /// writes the depth of the meshes whose material reads the scene depth once they are drawn
+    scene_depth_pipeline_layout: PipelineLayout,
+    scene_depth_pipeline: RenderPipeline,
+    depth_resolver: DepthResolver,
+    /// pipelines reloaded for the materials shared between meshes, which cannot be recompiled in place,
+    /// drawn instead of the pipeline of the material. See `reload_shaders`
+    shared_material_pipelines: HashMap<*const Material, (Weak<Material>, RenderPipeline)>,
+    /// the `Engine::shader_changes` the shaders were last reloaded for
+    seen_shader_changes: u64,
+    /// origin of the time of `CameraUniforms`
+    start_time: Instant,
+    /// the window of the engine the scene is drawn into
+    window_id: WindowId,
+    /// to give input to the viewport under the cursor
+    window_size: PhysicalSize<u32>,
+    cursor_position: Option<PhysicalPosition<f64>>,
 }
 
 impl Scene {
+    /// Creates a scene drawn into the main window of the engine.
     pub fn new(engine: &Engine) -> Scene {
-        let mut free_camera = FreeCamera::new(engine.window.inner_size().width as f32 / engine.window.inner_size().height as f32);
+        let window_id = engine.main_window().id();
+        Scene::for_window(window_id, engine)
+    }
+
+    /// Creates a scene drawn into the window `window_id` of the engine, see `Engine::add_window`.
+    pub fn for_window(window_id: WindowId, engine: &Engine) -> Scene {
+        let window_size = engine.window_by_id(window_id).size;
+        let mut free_camera = FreeCamera::new(window_size.width as f32 / window_size.height as f32);
         free_camera.tf().set_position(3.0, 1.5, 3.0);
 
+        let camera_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Uniform Buffer"),
+            contents: cast_slice(&[CameraUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Uniform Buffer"),
+            contents: cast_slice(&[LightUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let default_environment_map = Texture::from_rgba8(image::RgbaImage::new(1, 1), TextureOptions {
+            generate_mipmaps: false,
+            ..Default::default()
+        }, engine);
+        let mut shadow_map = ShadowMap::new(ShadowSettings::default(), engine);
+        let (scene_bind_group, cascade_bind_groups) = create_scene_bind_groups(&camera_uniform_buffer, &light_uniform_buffer, &default_environment_map, &shadow_map, engine);
+        shadow_map.cascade_bind_groups = cascade_bind_groups;
+
+        let scene_depth_pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Scene Depth Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &engine.layouts.mesh],
+            push_constant_ranges: &[],
+        });
+        let scene_depth_pipeline = create_scene_depth_pipeline(&scene_depth_pipeline_layout, engine)
+            .unwrap_or_else(|e| panic!("Could not create shader \"shadow\": {}", e));
+
         Scene {
             active_camera: free_camera.basic_camera,
             meshes: Vec::new(),
+            instanced_meshes: Vec::new(),
+            layers: u32::MAX,
+            render_textures: Vec::new(),
+            viewports: Vec::new(),
+            custom_render_passes: Vec::new(),
             execute_before_render: Box::new(|| {}),
+            render_mode: RenderMode::Solid,
+            debug_pipelines: DebugPipelines::new(engine),
+            debug_renderer: DebugRenderer::new(engine),
+            sun: DirectionalLight::default(),
+            ambient_light: HemisphereLight::default(),
+            environment_intensity: 1.0,
+            environment_map: None,
+            default_environment_map,
+            shadow_map,
+            atmosphere: None,
+            atmosphere_renderer: AtmosphereRenderer::new(engine),
+            fog: None,
+            fog_renderer: FogRenderer::new(engine),
+            depth_effect_bind_groups: HashMap::new(),
+            background: Background::default(),
+            background_renderer: BackgroundRenderer::new(engine),
+            post_process: PostProcessChain::new(engine),
+            camera_uniform_buffer,
+            light_uniform_buffer,
+            scene_bind_group,
+            scene_depth_pipeline_layout,
+            scene_depth_pipeline,
+            depth_resolver: DepthResolver::new(engine),
+            shared_material_pipelines: HashMap::new(),
+            seen_shader_changes: engine.shader_changes,
+            start_time: Instant::now(),
+            window_id,
+            window_size,
+            cursor_position: None,
+        }
+    }
+
+    /// Sets the equirectangular environment map lighting the physically based materials, None to only use `ambient_light`.
+    /// The texture should have mipmaps, sampled for rough reflections.
+    pub fn set_environment_map(&mut self, environment_map: Option<Rc<Texture>>, engine: &Engine) {
+        self.environment_map = environment_map;
+        self.rebuild_bind_groups(engine);
+    }
+
+    pub fn environment_map(&self) -> Option<&Rc<Texture>> {
+        self.environment_map.as_ref()
+    }
+
+    /// Recreates the shadow maps of the sun with new settings, `ShadowSettings::enabled` turning them off.
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings, engine: &Engine) {
+        self.shadow_map = ShadowMap::new(settings, engine);
+        self.rebuild_bind_groups(engine);
+    }
+
+    pub fn shadow_settings(&self) -> &ShadowSettings {
+        &self.shadow_map.settings
+    }
+
+    fn rebuild_bind_groups(&mut self, engine: &Engine) {
+        let environment_map = self.environment_map.as_deref().unwrap_or(&self.default_environment_map);
+        let (scene_bind_group, cascade_bind_groups) = create_scene_bind_groups(&self.camera_uniform_buffer, &self.light_uniform_buffer, environment_map, &self.shadow_map, engine);
+        self.scene_bind_group = scene_bind_group;
+        self.shadow_map.cascade_bind_groups = cascade_bind_groups;
+        // recreated by the next update
+        for render_texture in self.render_textures.iter_mut() {
+            render_texture.scene_bind_group = None;
+        }
+        for viewport in self.viewports.iter_mut() {
+            viewport.scene_bind_group = None;
         }
     }
 
@@ -32,9 +218,57 @@ impl Scene {
         self.meshes.last_mut().unwrap()
     }
 
+    pub fn add_instanced_mesh(&mut self, mesh: InstancedMesh) -> &mut InstancedMesh {
+        self.instanced_meshes.push(mesh);
+        self.instanced_meshes.last_mut().unwrap()
+    }
+
+    /// Adds a camera rendering the scene into a texture every frame, see `RenderTexture`.
+    pub fn add_render_texture(&mut self, render_texture: RenderTexture) -> &mut RenderTexture {
+        self.render_textures.push(render_texture);
+        self.render_textures.last_mut().unwrap()
+    }
+
+    /// Adds a region of the window showing the scene, see `Viewport`.
+    pub fn add_viewport(&mut self, viewport: Viewport) -> &mut Viewport {
+        self.viewports.push(viewport);
+        self.viewports.last_mut().unwrap()
+    }
+
+    /// The window of the engine the scene is drawn into.
+    pub fn window_id(&self) -> WindowId {
+        self.window_id
+    }
+
+    /// Immediate-mode debug drawing, everything drawn with it is cleared after the next frame.
+    pub fn debug(&mut self) -> &mut DebugRenderer {
+        &mut self.debug_renderer
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.active_camera.aspect_ratio = new_size.width as f32 / new_size.height as f32;
+            self.window_size = new_size;
+        }
+    }
+
+    /// The viewport under the cursor, if any.
+    fn viewport_under_cursor(&mut self) -> Option<&mut Viewport> {
+        let position = self.cursor_position?;
+        let (width, height) = (self.window_size.width, self.window_size.height);
+        self.viewports.iter_mut().find(|viewport| viewport.contains(position, width, height))
+    }
+
+    /// The camera given the input, the one of the viewport under the cursor or else the active camera.
+    fn camera_under_cursor(&mut self) -> &mut BasicCamera {
+        let position = self.cursor_position;
+        let (width, height) = (self.window_size.width, self.window_size.height);
+        let viewport_camera = self.viewports.iter_mut()
+            .find(|viewport| position.is_some_and(|position| viewport.contains(position, width, height)))
+            .and_then(|viewport| viewport.camera.as_mut());
+        match viewport_camera {
+            Some(camera) => camera,
+            None => &mut self.active_camera,
         }
     }
 
@@ -46,12 +280,19 @@ impl Scene {
             WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                 self.resize(**new_inner_size);
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some(*position);
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+            }
             WindowEvent::MouseWheel {
                 delta: MouseScrollDelta::LineDelta(_, y),
                 ..
             } => {
-                let out_dir = self.active_camera.transform.position.normalize();
-                self.active_camera.transform.position -= out_dir * *y * 0.1;
+                let camera = self.camera_under_cursor();
+                let out_dir = camera.transform.position.normalize();
+                camera.transform.position -= out_dir * *y * 0.1;
             }
             WindowEvent::KeyboardInput {
                 input:
@@ -63,11 +304,12 @@ impl Scene {
                 ..
             } => {
                 // rotate camera around the y axis
+                let camera = self.camera_under_cursor();
                 let rotation = cgmath::Quaternion::from_axis_angle(
                     cgmath::Vector3::unit_y(),
                     cgmath::Deg(-1.0),
                 );
-                self.active_camera.transform.position = rotation * self.active_camera.transform.position;
+                camera.transform.position = rotation * camera.transform.position;
             }
             WindowEvent::KeyboardInput {
                 input:
@@ -79,11 +321,12 @@ impl Scene {
                 ..
             } => {
                 // rotate camera around the y axis
+                let camera = self.camera_under_cursor();
                 let rotation = cgmath::Quaternion::from_axis_angle(
                     cgmath::Vector3::unit_y(),
                     cgmath::Deg(1.0),
                 );
-                self.active_camera.transform.position = rotation * self.active_camera.transform.position;
+                camera.transform.position = rotation * camera.transform.position;
             },
             WindowEvent::KeyboardInput {
                 input:
@@ -95,11 +338,12 @@ impl Scene {
                 ..
             } => {
                 // rotate camera around the x axis
+                let camera = self.camera_under_cursor();
                 let rotation = cgmath::Quaternion::from_axis_angle(
-                    self.active_camera.transform.right(),
+                    camera.transform.right(),
                     cgmath::Deg(-1.0),
                 );
-                self.active_camera.transform.position = rotation * self.active_camera.transform.position;
+                camera.transform.position = rotation * camera.transform.position;
             },
             WindowEvent::KeyboardInput {
                 input:
@@ -111,11 +355,12 @@ impl Scene {
                 ..
             } => {
                 // rotate camera around the x axis
+                let camera = self.camera_under_cursor();
                 let rotation = cgmath::Quaternion::from_axis_angle(
-                    self.active_camera.transform.right(),
+                    camera.transform.right(),
                     cgmath::Deg(1.0),
                 );
-                self.active_camera.transform.position = rotation * self.active_camera.transform.position;
+                camera.transform.position = rotation * camera.transform.position;
             }
             WindowEvent::KeyboardInput {
                 input:
@@ -128,84 +373,578 @@ impl Scene {
             } => {
                 //let mesh = self.meshes.first_mut().unwrap();
                 //mesh.transform.position += mesh.transform.forward() * ANIMATION_SPEED;
-                let camera = &mut self.active_camera;
+                let camera = self.camera_under_cursor();
                 camera.transform.position -= camera.transform.forward() * ANIMATION_SPEED;
             }
+            WindowEvent::KeyboardInput {
+                input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::Tab),
+                    ..
+                },
+                ..
+            } => {
+                let scene_render_mode = self.render_mode;
+                match self.viewport_under_cursor() {
+                    Some(viewport) => {
+                        viewport.render_mode = Some(viewport.render_mode.unwrap_or(scene_render_mode).next());
+                    }
+                    None => self.render_mode = self.render_mode.next(),
+                }
+            }
             _ => {}
         }
     }
 
     pub fn update(&mut self, engine: &mut Engine) {
+        self.reload_shaders(engine);
+
+        let time = self.start_time.elapsed().as_secs_f32();
+        let config = &engine.window_by_id(self.window_id).config;
+        let (window_width, window_height) = (config.width, config.height);
+        for viewport in self.viewports.iter_mut() {
+            viewport.update(&self.active_camera, window_width, window_height, time, engine);
+        }
+        let camera_uniforms = CameraUniforms::from_camera(&self.active_camera, time);
+        engine.queue.write_buffer(&self.camera_uniform_buffer, 0, cast_slice(&[camera_uniforms]));
+        for render_texture in self.render_textures.iter_mut() {
+            render_texture.update(time, engine);
+        }
+        let environment_map = self.environment_map.as_deref().unwrap_or(&self.default_environment_map);
+        for render_texture in self.render_textures.iter_mut().filter(|render_texture| render_texture.scene_bind_group.is_none()) {
+            render_texture.scene_bind_group = Some(create_scene_bind_group(&render_texture.camera_uniform_buffer, &self.light_uniform_buffer, environment_map,
+                                                                           &self.shadow_map, &self.shadow_map.view, "Render Texture Scene Bind Group", engine));
+        }
+        for viewport in self.viewports.iter_mut().filter(|viewport| viewport.scene_bind_group.is_none()) {
+            viewport.scene_bind_group = Some(create_scene_bind_group(&viewport.camera_uniform_buffer, &self.light_uniform_buffer, environment_map,
+                                                                     &self.shadow_map, &self.shadow_map.view, "Viewport Scene Bind Group", engine));
+        }
+        // the targets of a view are recreated when it is resized, the bind groups of the previous ones being dropped
+        let view_targets: Vec<&RenderTargets> = iter::once(&engine.window_by_id(self.window_id).render_targets)
+            .chain(self.render_textures.iter().map(|render_texture| &render_texture.targets))
+            .chain(self.viewports.iter().map(|viewport| &viewport.targets))
+            .collect();
+        self.depth_effect_bind_groups.retain(|id, _| view_targets.iter().any(|targets| targets.id == *id));
+        for targets in view_targets {
+            self.depth_effect_bind_groups.entry(targets.id).or_insert_with(|| (
+                self.fog_renderer.create_bind_group(&targets.depth.view, engine),
+                self.atmosphere_renderer.create_bind_group(&targets.depth.view, engine),
+            ));
+        }
+
+        let light_uniforms = match &self.environment_map {
+            Some(environment_map) => LightUniforms::new(&self.sun, &self.ambient_light, self.environment_intensity, environment_map.mip_level_count),
+            None => LightUniforms::new(&self.sun, &self.ambient_light, 0.0, 1),
+        };
+        engine.queue.write_buffer(&self.light_uniform_buffer, 0, cast_slice(&[light_uniforms]));
+        self.shadow_map.update(&self.active_camera, &self.sun, engine);
+        if let Some(atmosphere) = &self.atmosphere {
+            self.atmosphere_renderer.update(atmosphere, engine);
+        }
+        self.background_renderer.update(&self.background, engine);
+        if let Some(fog) = &self.fog {
+            self.fog_renderer.update(fog, self.background.average_color(), engine);
+        }
+        self.post_process.update(engine);
+
         for mesh in self.meshes.iter() {
-            let mvp_mat = self.active_camera.get_projection_matrix() * self.active_camera.get_view_matrix() * mesh.transform.compute_world_matrix();
-            let mvp_ref: &[f32; 16] = mvp_mat.as_ref();
-            engine.queue.write_buffer(&mesh.material.vertex_uniform_buffer, 0, cast_slice(mvp_ref));
+            mesh.update_uniforms(engine);
+
+            let has_uv = mesh.has_attribute(VertexAttribute::Uv);
+            self.debug_pipelines.prepare(mesh.render_mode.unwrap_or(self.render_mode), has_uv, engine);
+            for render_mode in self.viewports.iter().filter_map(|viewport| viewport.render_mode) {
+                self.debug_pipelines.prepare(mesh.render_mode.unwrap_or(render_mode), has_uv, engine);
+            }
+            self.debug_renderer.add_mesh(mesh);
+        }
+        for instanced_mesh in self.instanced_meshes.iter() {
+            instanced_mesh.mesh.update_uniforms(engine);
         }
+        self.debug_renderer.add_helpers();
 
         (self.execute_before_render)();
     }
 
+    /// Recompiles the materials of the scene when the engine's shader watcher reports modified files,
+    /// see `Engine::shader_changes`. Every shader is recompiled, so a scene that missed a change, updated after
+    /// another scene polled the next one, reloads it with the latest. Materials failing to compile keep their previous pipeline.
+    fn reload_shaders(&mut self, engine: &mut Engine) {
+        engine.poll_shader_changes();
+        if engine.shader_changes == self.seen_shader_changes { return; }
+        self.seen_shader_changes = engine.shader_changes;
+
+        self.debug_pipelines.clear();
+        self.debug_renderer.recompile(engine);
+        if let Err(e) = self.atmosphere_renderer.recompile(engine) {
+            eprintln!("Could not reload shader \"atmosphere\", keeping the previous pipeline: {}", e);
+        }
+        if let Err(e) = self.fog_renderer.recompile(engine) {
+            eprintln!("Could not reload shader \"fog\", keeping the previous pipeline: {}", e);
+        }
+        if let Err(e) = self.background_renderer.recompile(engine) {
+            eprintln!("Could not reload shader \"background\", keeping the previous pipeline: {}", e);
+        }
+        self.post_process.recompile(engine);
+        if let Err(e) = self.depth_resolver.recompile(engine) {
+            eprintln!("Could not reload shader \"depth_resolve\", keeping the previous pipeline: {}", e);
+        }
+        if let Err(e) = self.shadow_map.recompile(engine) {
+            eprintln!("Could not reload shader \"shadow\", keeping the previous pipeline: {}", e);
+        }
+        match create_scene_depth_pipeline(&self.scene_depth_pipeline_layout, engine) {
+            Ok(pipeline) => self.scene_depth_pipeline = pipeline,
+            Err(e) => eprintln!("Could not reload shader \"shadow\" for the scene depth, keeping the previous pipeline: {}", e),
+        }
+
+        for file in &engine.changed_shaders {
+            eprintln!("Shader changed: {}", file.display());
+        }
+
+        // the weak references keep the addresses of dropped materials from being reused
+        self.shared_material_pipelines.retain(|_, (material, _)| material.strong_count() > 0);
+        let mut reloaded_materials = Vec::new();
+        for mesh in self.meshes.iter_mut() {
+            let key = Rc::as_ptr(mesh.material());
+            if reloaded_materials.contains(&key) { continue; }
+            reloaded_materials.push(key);
+
+            // a weak reference alone prevents `get_mut`, so the previous reloaded pipeline is taken out first
+            let previous_pipeline = self.shared_material_pipelines.remove(&key);
+            let result = match Rc::get_mut(&mut mesh.material) {
+                Some(material) => material.recompile(engine),
+                None => mesh.material().compile(engine).map(|(_, pipeline)| {
+                    self.shared_material_pipelines.insert(key, (Rc::downgrade(mesh.material()), pipeline));
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Could not reload shader \"{}\", keeping the previous pipeline: {}", mesh.material().shader_name, e);
+                if let Some(previous_pipeline) = previous_pipeline {
+                    self.shared_material_pipelines.insert(key, previous_pipeline);
+                }
+            }
+        }
+        for instanced_mesh in self.instanced_meshes.iter_mut() {
+            if let Err(e) = instanced_mesh.recompile(engine) {
+                eprintln!("Could not reload shader \"{}\" for instancing, keeping the previous pipeline: {}", instanced_mesh.mesh.material().shader_name, e);
+            }
+        }
+    }
+
+    /// Renders a frame through a render graph: the shadow maps, the render textures, then the scene on screen,
+    /// filling the window or split into `viewports`, with the passes of `custom_render_passes` added to the graph of every camera.
     pub fn render(&mut self, engine: &mut Engine) -> Result<(), wgpu::SurfaceError> {
         //let output = self.init.surface.get_current_frame()?.output;
-        let output = engine.surface.get_current_texture()?;
+        let output = match engine.window_by_id(self.window_id).surface.get_current_texture() {
+            Ok(output) => output,
+            Err(e) => {
+                self.debug_renderer.clear();
+                return Err(e);
+            }
+        };
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        let depth_texture = engine.device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: engine.config.width,
-                height: engine.config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth24Plus,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            label: None,
-        });
-        let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.debug_renderer.upload(&self.active_camera, engine);
 
         let mut encoder = engine.device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
-        {
+        // the pool is lent to the graph, whose passes borrow the engine
+        let mut texture_pool = std::mem::take(&mut engine.window_by_id_mut(self.window_id).texture_pool);
+        let engine_ref: &Engine = engine;
+        let scene: &Scene = self;
+        let window = engine_ref.window_by_id(scene.window_id);
+        // the render textures and the viewports added since the last update are drawn from the next frame
+        let render_textures: Vec<(&RenderTexture, &BindGroup)> = scene.render_textures.iter()
+            .filter(|render_texture| render_texture.enabled)
+            .filter_map(|render_texture| Some((render_texture, render_texture.scene_bind_group.as_ref()?)))
+            .collect();
+        let viewports: Vec<(&Viewport, &BindGroup)> = scene.viewports.iter()
+            .filter_map(|viewport| Some((viewport, viewport.scene_bind_group.as_ref()?)))
+            .collect();
+
+        let mut graph = RenderGraph::new();
+        let shadow_map = graph.import_texture(&scene.shadow_map.view);
+        if scene.shadow_map.settings.enabled {
+            for (cascade_view, cascade_bind_group) in scene.shadow_map.cascade_views.iter().zip(scene.shadow_map.cascade_bind_groups.iter()) {
+                graph.add_pass("Shadow Pass", &[], &[shadow_map], move |encoder, _| {
+                    let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Shadow Pass"),
+                        color_attachments: &[],
+                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                            view: cascade_view,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        }),
+                    });
+                    shadow_pass.set_bind_group(0, cascade_bind_group, &[]);
+                    for mesh in scene.meshes.iter().filter(|mesh| mesh.cast_shadows) {
+                        mesh.draw_with_pipeline(scene.shadow_map.pipeline(), &SHADOW_ATTRIBUTES, &mut shadow_pass);
+                    }
+                    for instanced_mesh in scene.instanced_meshes.iter().filter(|instanced_mesh| instanced_mesh.mesh.cast_shadows) {
+                        instanced_mesh.draw_with_pipeline(scene.shadow_map.instanced_pipeline(), &SHADOW_ATTRIBUTES, &mut shadow_pass);
+                    }
+                });
+            }
+        }
+
+        let mut render_texture_outputs = Vec::new();
+        for &(render_texture, scene_bind_group) in render_textures.iter() {
+            let output = graph.import_texture(&render_texture.texture().view);
+            let view_textures = scene.add_view_passes(&mut graph, View {
+                scene_bind_group,
+                targets: &render_texture.targets,
+                output,
+                output_format: RENDER_TEXTURE_FORMAT,
+                layers: render_texture.layers,
+                render_mode: scene.render_mode,
+                shadow_map,
+                sampled_textures: Vec::new(),
+            }, engine_ref);
+            render_texture_outputs.push(view_textures.output);
+        }
+
+        // the materials of the screen may sample the render textures
+        let surface = graph.import_texture(&view);
+        if scene.viewports.is_empty() {
+            let view_textures = scene.add_view_passes(&mut graph, View {
+                scene_bind_group: &scene.scene_bind_group,
+                targets: &window.render_targets,
+                output: surface,
+                output_format: engine_ref.surface_format,
+                layers: scene.layers,
+                render_mode: scene.render_mode,
+                shadow_map,
+                sampled_textures: render_texture_outputs,
+            }, engine_ref);
+            scene.add_overlay_pass(&mut graph, &view_textures, &scene.scene_bind_group);
+        } else {
+            // each viewport is drawn into a texture of the format of the surface, then copied into its region
+            graph.add_pass("Clear Pass", &[], &[surface], |encoder, textures| {
+                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Clear Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: textures.view(surface),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+            });
+            for &(viewport, scene_bind_group) in viewports.iter() {
+                let rect = viewport.pixel_rect(window.config.width, window.config.height);
+                let output = graph.create_texture(TransientTexture {
+                    width: viewport.targets.width,
+                    height: viewport.targets.height,
+                    format: engine_ref.surface_format,
+                });
+                let view_textures = scene.add_view_passes(&mut graph, View {
+                    scene_bind_group,
+                    targets: &viewport.targets,
+                    output,
+                    output_format: engine_ref.surface_format,
+                    layers: viewport.layers,
+                    render_mode: viewport.render_mode.unwrap_or(scene.render_mode),
+                    shadow_map,
+                    sampled_textures: render_texture_outputs.clone(),
+                }, engine_ref);
+                scene.add_overlay_pass(&mut graph, &view_textures, scene_bind_group);
+                scene.post_process.add_copy_pass(&mut graph, &view_textures, surface, rect, scene_bind_group, engine_ref);
+            }
+        }
+
+        graph.execute(&mut encoder, &mut texture_pool, &engine_ref.device);
+        engine.window_by_id_mut(self.window_id).texture_pool = texture_pool;
+
+        engine.queue.submit(iter::once(encoder.finish()));
+        output.present();
+
+        self.debug_renderer.clear();
+
+        Ok(())
+    }
+
+    /// Adds the passes drawing the meshes of `view.layers` seen by the camera of `view.scene_bind_group`,
+    /// from the main pass to the post-processing writing into `view.output`, and returns the textures of the view.
+    fn add_view_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, view: View<'a>, engine: &'a Engine) -> ViewTextures {
+        let targets = view.targets;
+        let (hdr, depth) = (graph.import_texture(&targets.hdr.view), graph.import_texture(&targets.depth.view));
+        let multisampled = targets.multisampled.as_ref()
+            .map(|(color, depth)| (graph.import_texture(&color.view), graph.import_texture(&depth.view)));
+        let view_textures = ViewTextures {
+            hdr,
+            depth,
+            output: view.output,
+            output_format: view.output_format,
+            width: targets.width,
+            height: targets.height,
+        };
+        let (layers, view_render_mode) = (view.layers, view.render_mode);
+        let scene_bind_group = view.scene_bind_group;
+        let meshes = move || self.meshes.iter().filter(move |mesh| mesh.layers & layers != 0);
+
+        let mut main_reads = vec![view.shadow_map];
+        main_reads.extend_from_slice(&view.sampled_textures);
+        // with multisampling the main pass draws into multisampled buffers, the color being resolved into `hdr`
+        let main_writes = match multisampled {
+            Some((color, depth)) => vec![color, depth, hdr],
+            None => vec![hdr, depth],
+        };
+        graph.add_pass("Render Pass", &main_reads, &main_writes, move |encoder, textures| {
+            let (color_view, resolve_target, depth_view) = match multisampled {
+                Some((color, depth)) => (textures.view(color), Some(textures.view(hdr)), textures.view(depth)),
+                None => (textures.view(hdr), None, textures.view(depth)),
+            };
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.2,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.background.clear_color()),
                         store: true,
                     },
                 })],
                 //depth_stencil_attachment: None,
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &depth_view,
+                    view: depth_view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
-                        store: false,
+                        store: true,
                     }),
                     stencil_ops: None,
                 }),
             });
 
-            for mesh in &self.meshes {
-                mesh.draw(&mut render_pass);
+            render_pass.set_bind_group(0, scene_bind_group, &[]);
+            self.background_renderer.draw(&mut render_pass);
+
+            for mesh in meshes() {
+                let render_mode = mesh.render_mode.unwrap_or(view_render_mode);
+                let debug_pipeline = self.debug_pipelines.get(render_mode, mesh.has_attribute(VertexAttribute::Uv));
+
+                if (render_mode.draws_material() || debug_pipeline.is_none()) && !mesh.material().reads_scene_depth {
+                    self.draw_mesh(mesh, &mut render_pass);
+                }
+                if let Some((pipeline, attributes)) = debug_pipeline {
+                    mesh.draw_with_pipeline(pipeline, attributes, &mut render_pass);
+                }
+            }
+            for instanced_mesh in self.instanced_meshes.iter().filter(|instanced_mesh| instanced_mesh.mesh.layers & layers != 0) {
+                instanced_mesh.draw(&mut render_pass);
             }
+        });
+
+        if let Some((_, multisampled_depth)) = multisampled {
+            graph.add_pass("Depth Resolve Pass", &[multisampled_depth], &[depth], move |encoder, textures| {
+                self.depth_resolver.resolve(encoder, textures.view(multisampled_depth), textures.view(depth), engine);
+            });
         }
 
-        engine.queue.submit(iter::once(encoder.finish()));
-        output.present();
+        // meshes reading the depth buffer, such as water, are blended over the others then write their own depth
+        let transparent_meshes = move || meshes().filter(move |mesh| {
+            let render_mode = mesh.render_mode.unwrap_or(view_render_mode);
+            mesh.material().reads_scene_depth && (render_mode.draws_material() || self.debug_pipelines.get(render_mode, mesh.has_attribute(VertexAttribute::Uv)).is_none())
+        });
+        if transparent_meshes().next().is_some() {
+            graph.add_pass("Transparent Pass", &[view.shadow_map, depth], &[hdr, depth], move |encoder, textures| {
+                {
+                    let mut transparent_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Transparent Pass"),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: textures.view(hdr),
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Load,
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                    });
+                    transparent_pass.set_bind_group(0, scene_bind_group, &[]);
+                    transparent_pass.set_bind_group(3, &targets.scene_depth_bind_group, &[]);
+                    for mesh in transparent_meshes() {
+                        self.draw_mesh(mesh, &mut transparent_pass);
+                    }
+                }
 
-        Ok(())
+                let mut transparent_depth_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Transparent Depth Pass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: textures.view(depth),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                transparent_depth_pass.set_bind_group(0, scene_bind_group, &[]);
+                for mesh in transparent_meshes() {
+                    mesh.draw_with_pipeline(&self.scene_depth_pipeline, &SHADOW_ATTRIBUTES, &mut transparent_depth_pass);
+                }
+            });
+        }
+
+        // the fog and the atmosphere read the depth buffer, so they are drawn in their own pass,
+        // skipped for the views added since the last update
+        let depth_effect_bind_groups = self.depth_effect_bind_groups.get(&targets.id)
+            .filter(|_| self.fog.is_some() || self.atmosphere.is_some());
+        if let Some((fog_bind_group, atmosphere_bind_group)) = depth_effect_bind_groups {
+            graph.add_pass("Fog And Atmosphere Pass", &[depth], &[hdr], move |encoder, textures| {
+                let mut effects_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Fog And Atmosphere Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: textures.view(hdr),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                effects_pass.set_bind_group(0, scene_bind_group, &[]);
+                if self.fog.is_some() {
+                    self.fog_renderer.draw(fog_bind_group, &mut effects_pass);
+                }
+                if self.atmosphere.is_some() {
+                    self.atmosphere_renderer.draw(atmosphere_bind_group, &mut effects_pass);
+                }
+            });
+        }
+
+        self.post_process.add_passes(graph, &view_textures, scene_bind_group, engine);
+        // added last, the passes drawing over the scene still run before the post-processing reading it
+        for custom_render_pass in &self.custom_render_passes {
+            custom_render_pass.add_passes(graph, &view_textures, scene_bind_group, engine);
+        }
+
+        view_textures
+    }
+
+    /// Adds the pass drawing the debug drawings over the output of `view`, which are not post-processed.
+    fn add_overlay_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, view: &ViewTextures, scene_bind_group: &'a BindGroup) {
+        let (depth, output) = (view.depth, view.output);
+        graph.add_pass("Overlay Pass", &[depth], &[output], move |encoder, textures| {
+            let mut overlay_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: textures.view(output),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: textures.view(depth),
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            overlay_pass.set_bind_group(0, scene_bind_group, &[]);
+            self.debug_renderer.draw(&mut overlay_pass);
+        });
     }
+
+    /// Draws `mesh` with its material, or with the pipeline reloaded for it if the material is shared.
+    fn draw_mesh<'a>(&'a self, mesh: &'a Mesh, render_pass: &mut RenderPass<'a>) {
+        match self.shared_material_pipelines.get(&Rc::as_ptr(mesh.material())) {
+            Some((_, pipeline)) => mesh.draw_with_material_pipeline(pipeline, render_pass),
+            None => mesh.draw(render_pass),
+        }
+    }
+}
+
+/// A camera of the scene and the textures it renders into, see `Scene::add_view_passes`.
+struct View<'a> {
+    /// group 0 binding the camera
+    scene_bind_group: &'a BindGroup,
+    targets: &'a RenderTargets,
+    /// the surface, a render texture or the texture of a viewport
+    output: TextureId,
+    output_format: wgpu::TextureFormat,
+    /// bit mask of the layers of the meshes drawn, see `Mesh::layers`
+    layers: u32,
+    /// render mode of the meshes without their own
+    render_mode: RenderMode,
+    shadow_map: TextureId,
+    /// textures of the graph the materials may sample, such as the render textures
+    sampled_textures: Vec<TextureId>,
+}
+
+/// Creates the group 0 of the main pass and the ones of the shadow cascades,
+/// binding the camera of each cascade and an empty shadow map instead of the one being rendered.
+fn create_scene_bind_groups(camera_uniform_buffer: &Buffer, light_uniform_buffer: &Buffer, environment_map: &Texture, shadow_map: &ShadowMap, engine: &Engine) -> (BindGroup, Vec<BindGroup>) {
+    let scene_bind_group = create_scene_bind_group(camera_uniform_buffer, light_uniform_buffer, environment_map, shadow_map, &shadow_map.view, "Scene Bind Group", engine);
+    let cascade_bind_groups = shadow_map.cascade_camera_buffers.iter()
+        .map(|buffer| create_scene_bind_group(buffer, light_uniform_buffer, environment_map, shadow_map, &shadow_map.placeholder_view, "Shadow Cascade Bind Group", engine))
+        .collect();
+
+    (scene_bind_group, cascade_bind_groups)
+}
+
+fn create_scene_bind_group(camera_uniform_buffer: &Buffer, light_uniform_buffer: &Buffer, environment_map: &Texture, shadow_map: &ShadowMap, shadow_view: &TextureView, label: &str, engine: &Engine) -> BindGroup {
+    engine.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &engine.layouts.scene,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: light_uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&environment_map.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&environment_map.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: shadow_map.uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(shadow_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+            },
+        ],
+        label: Some(label),
+    })
+}
+
+/// Depth only pipeline writing the depth of meshes into the depth buffer of the main pass, using the vertex shader of the shadow pass.
+fn create_scene_depth_pipeline(pipeline_layout: &PipelineLayout, engine: &Engine) -> Result<RenderPipeline, ShaderError> {
+    let mut preprocessor = ShaderPreprocessor::new();
+    preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+    let settings = PipelineSettings {
+        depth_only: true,
+        multisampled: false,
+        ..Default::default()
+    };
+    let (_, pipeline) = create_pipeline("shadow", &preprocessor, pipeline_layout, &VertexBufferLayouts::new(&SHADOW_ATTRIBUTES).desc(), &settings, engine)?;
+    Ok(pipeline)
 }
@@ -0,0 +1,360 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Location of the shader sources in this repository, useful to enable hot-reloading during development.
+pub const SHADER_SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// Returns the WGSL source of a shader or chunk embedded in the binary, if `name` is known.
+/// Shaders live in `src/shaders` and chunks, meant to be pulled in with `#include`, in `src/shaders/chunks`.
+pub fn embedded_source(name: &str) -> Option<&'static str> {
+    match name {
+        "default" => Some(include_str!("shaders/default.wgsl")),
+        "terrain" => Some(include_str!("shaders/terrain.wgsl")),
+        "standard" => Some(include_str!("shaders/standard.wgsl")),
+        "debug" => Some(include_str!("shaders/debug.wgsl")),
+        "debug_lines" => Some(include_str!("shaders/debug_lines.wgsl")),
+        "shadow" => Some(include_str!("shaders/shadow.wgsl")),
+        "atmosphere" => Some(include_str!("shaders/atmosphere.wgsl")),
+        "fog" => Some(include_str!("shaders/fog.wgsl")),
+        "background" => Some(include_str!("shaders/background.wgsl")),
+        "water" => Some(include_str!("shaders/water.wgsl")),
+        "tone_mapping" => Some(include_str!("shaders/tone_mapping.wgsl")),
+        "color_grading" => Some(include_str!("shaders/color_grading.wgsl")),
+        "fxaa" => Some(include_str!("shaders/fxaa.wgsl")),
+        "bloom" => Some(include_str!("shaders/bloom.wgsl")),
+        "ssao" => Some(include_str!("shaders/ssao.wgsl")),
+        "present" => Some(include_str!("shaders/present.wgsl")),
+        "depth_resolve" => Some(include_str!("shaders/depth_resolve.wgsl")),
+        "uniforms" => Some(include_str!("shaders/chunks/uniforms.wgsl")),
+        "vertex" => Some(include_str!("shaders/chunks/vertex.wgsl")),
+        "lighting" => Some(include_str!("shaders/chunks/lighting.wgsl")),
+        "noise" => Some(include_str!("shaders/chunks/noise.wgsl")),
+        "tonemapping" => Some(include_str!("shaders/chunks/tonemapping.wgsl")),
+        "triplanar" => Some(include_str!("shaders/chunks/triplanar.wgsl")),
+        "pbr" => Some(include_str!("shaders/chunks/pbr.wgsl")),
+        "shadows" => Some(include_str!("shaders/chunks/shadows.wgsl")),
+        "fullscreen" => Some(include_str!("shaders/chunks/fullscreen.wgsl")),
+        "depth" => Some(include_str!("shaders/chunks/depth.wgsl")),
+        "post_process" => Some(include_str!("shaders/chunks/post_process.wgsl")),
+        "normal_mapping" => Some(include_str!("shaders/chunks/normal_mapping.wgsl")),
+        _ => None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderError {
+    UnknownShader(String),
+    UnknownInclude { line: usize, chunk: String },
+    UnknownDirective { line: usize, directive: String },
+    MissingArgument { line: usize, directive: String },
+    UnexpectedElse { line: usize },
+    UnexpectedEndif { line: usize },
+    UnterminatedIf { shader: String },
+    CyclicInclude(String),
+    Io { path: PathBuf, message: String },
+    Compilation(String),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::UnknownShader(name) => write!(f, "unknown shader or chunk \"{}\"", name),
+            ShaderError::UnknownInclude { line, chunk } => write!(f, "line {}: unknown chunk \"{}\"", line, chunk),
+            ShaderError::UnknownDirective { line, directive } => write!(f, "line {}: unknown directive #{}", line, directive),
+            ShaderError::MissingArgument { line, directive } => write!(f, "line {}: #{} expects an argument", line, directive),
+            ShaderError::UnexpectedElse { line } => write!(f, "line {}: #else without matching #ifdef", line),
+            ShaderError::UnexpectedEndif { line } => write!(f, "line {}: #endif without matching #ifdef", line),
+            ShaderError::UnterminatedIf { shader } => write!(f, "unterminated #ifdef in \"{}\"", shader),
+            ShaderError::CyclicInclude(name) => write!(f, "\"{}\" includes itself", name),
+            ShaderError::Io { path, message } => write!(f, "could not read {}: {}", path.display(), message),
+            ShaderError::Compilation(message) => write!(f, "compilation failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// A small WGSL preprocessor supporting `#include "chunk"`, `#define NAME [value]`, `#undef NAME`,
+/// `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`.
+/// Each chunk is included at most once per shader, so chunks can include the ones they depend on.
+/// Defines with a value are substituted wherever their name appears as a whole identifier.
+/// When `shader_dir` is set, shaders and chunks are read from `<shader_dir>/<name>.wgsl` or `<shader_dir>/chunks/<name>.wgsl`
+/// before falling back to the sources embedded in the binary.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderPreprocessor {
+    pub defines: HashMap<String, String>,
+    pub shader_dir: Option<PathBuf>,
+}
+
+struct ConditionalBlock {
+    /// whether the enclosing block is emitting lines
+    parent_active: bool,
+    /// whether the current branch of this block is emitting lines
+    active: bool,
+    seen_else: bool,
+}
+
+impl ShaderPreprocessor {
+    pub fn new() -> ShaderPreprocessor {
+        ShaderPreprocessor::default()
+    }
+
+    /// Defines a flag tested by `#ifdef` or, when `value` is not empty, a token to substitute.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.defines.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.defines.contains_key(name)
+    }
+
+    /// Resolves the shader `name` and all of its includes into plain WGSL.
+    pub fn process(&self, name: &str) -> Result<String, ShaderError> {
+        let source = self.load(name)?;
+        self.process_source(name, &source)
+    }
+
+    /// Same as `process` but for a source string that is not registered under a name.
+    pub fn process_source(&self, name: &str, source: &str) -> Result<String, ShaderError> {
+        let mut defines = self.defines.clone();
+        let mut included = HashSet::new();
+        let mut include_stack = vec![name.to_string()];
+        let mut output = String::with_capacity(source.len());
+
+        self.process_into(name, source, &mut defines, &mut included, &mut include_stack, &mut output)?;
+
+        Ok(output)
+    }
+
+    fn load(&self, name: &str) -> Result<String, ShaderError> {
+        if let Some(shader_dir) = &self.shader_dir {
+            let file_name = format!("{}.wgsl", name);
+            for path in [shader_dir.join(&file_name), shader_dir.join("chunks").join(&file_name)] {
+                if path.is_file() {
+                    return fs::read_to_string(&path).map_err(|e| ShaderError::Io { path, message: e.to_string() });
+                }
+            }
+        }
+
+        embedded_source(name)
+            .map(str::to_string)
+            .ok_or_else(|| ShaderError::UnknownShader(name.to_string()))
+    }
+
+    fn process_into(
+        &self,
+        name: &str,
+        source: &str,
+        defines: &mut HashMap<String, String>,
+        included: &mut HashSet<String>,
+        include_stack: &mut Vec<String>,
+        output: &mut String,
+    ) -> Result<(), ShaderError> {
+        let mut blocks: Vec<ConditionalBlock> = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let active = blocks.last().is_none_or(|block| block.active);
+            let trimmed = line.trim_start();
+
+            if let Some(directive_line) = trimmed.strip_prefix('#') {
+                let mut parts = directive_line.splitn(2, char::is_whitespace);
+                let directive = parts.next().unwrap_or("");
+                let argument = parts.next().map(str::trim).unwrap_or("");
+
+                let require_argument = || {
+                    if argument.is_empty() {
+                        Err(ShaderError::MissingArgument { line: line_number, directive: directive.to_string() })
+                    } else {
+                        Ok(argument)
+                    }
+                };
+
+                match directive {
+                    "ifdef" | "ifndef" => {
+                        let symbol = require_argument()?;
+                        let condition = defines.contains_key(symbol) == (directive == "ifdef");
+                        blocks.push(ConditionalBlock { parent_active: active, active: active && condition, seen_else: false });
+                    }
+                    "else" => {
+                        let block = blocks.last_mut().ok_or(ShaderError::UnexpectedElse { line: line_number })?;
+                        if block.seen_else {
+                            return Err(ShaderError::UnexpectedElse { line: line_number });
+                        }
+                        block.seen_else = true;
+                        block.active = block.parent_active && !block.active;
+                    }
+                    "endif" => {
+                        blocks.pop().ok_or(ShaderError::UnexpectedEndif { line: line_number })?;
+                    }
+                    _ if !active => {}
+                    "include" => {
+                        let chunk = require_argument()?.trim_matches(|c| c == '"' || c == '<' || c == '>');
+                        if include_stack.iter().any(|entry| entry == chunk) {
+                            return Err(ShaderError::CyclicInclude(chunk.to_string()));
+                        }
+                        if included.insert(chunk.to_string()) {
+                            let chunk_source = self.load(chunk).map_err(|error| match error {
+                                ShaderError::UnknownShader(chunk) => ShaderError::UnknownInclude { line: line_number, chunk },
+                                error => error,
+                            })?;
+                            include_stack.push(chunk.to_string());
+                            self.process_into(chunk, &chunk_source, defines, included, include_stack, output)?;
+                            include_stack.pop();
+                        }
+                    }
+                    "define" => {
+                        let mut definition = require_argument()?.splitn(2, char::is_whitespace);
+                        let symbol = definition.next().unwrap_or("");
+                        let value = definition.next().map(str::trim).unwrap_or("");
+                        defines.insert(symbol.to_string(), value.to_string());
+                    }
+                    "undef" => {
+                        defines.remove(require_argument()?);
+                    }
+                    _ => {
+                        return Err(ShaderError::UnknownDirective { line: line_number, directive: directive.to_string() });
+                    }
+                }
+                continue;
+            }
+
+            if active {
+                output.push_str(&substitute_defines(line, defines));
+                output.push('\n');
+            }
+        }
+
+        if blocks.is_empty() {
+            Ok(())
+        } else {
+            Err(ShaderError::UnterminatedIf { shader: name.to_string() })
+        }
+    }
+}
+
+/// Replaces every identifier of `line` that is defined with a non empty value.
+fn substitute_defines(line: &str, defines: &HashMap<String, String>) -> String {
+    if defines.values().all(String::is_empty) {
+        return line.to_string();
+    }
+
+    let mut result = String::with_capacity(line.len());
+    let mut identifier = String::new();
+    let flush = |identifier: &mut String, result: &mut String| {
+        match defines.get(identifier.as_str()) {
+            Some(value) if !value.is_empty() => result.push_str(value),
+            _ => result.push_str(identifier),
+        }
+        identifier.clear();
+    };
+
+    for c in line.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            identifier.push(c);
+        } else {
+            flush(&mut identifier, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut identifier, &mut result);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::{ShaderError, ShaderPreprocessor};
+
+    /// A preprocessor reading the chunks `files` from a directory of its own.
+    fn with_chunks(test: &str, files: &[(&str, &str)]) -> ShaderPreprocessor {
+        let shader_dir = std::env::temp_dir().join(format!("gilgamesh-shader-tests-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&shader_dir).unwrap();
+        for (name, source) in files {
+            fs::write(shader_dir.join(format!("{}.wgsl", name)), source).unwrap();
+        }
+        ShaderPreprocessor { shader_dir: Some(shader_dir), ..Default::default() }
+    }
+
+    fn lines(output: &str) -> Vec<&str> {
+        output.lines().map(str::trim).filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let source = "\
+#ifdef A
+a
+#ifdef B
+ab
+#else
+a_not_b
+#endif
+#else
+not_a
+#ifndef B
+not_a_not_b
+#endif
+#endif
+";
+        let mut preprocessor = ShaderPreprocessor::new();
+        assert_eq!(lines(&preprocessor.process_source("test", source).unwrap()), ["not_a", "not_a_not_b"]);
+        preprocessor.define("A", "");
+        assert_eq!(lines(&preprocessor.process_source("test", source).unwrap()), ["a", "a_not_b"]);
+        preprocessor.define("B", "");
+        assert_eq!(lines(&preprocessor.process_source("test", source).unwrap()), ["a", "ab"]);
+    }
+
+    #[test]
+    fn defines_are_substituted_as_whole_identifiers() {
+        let mut preprocessor = ShaderPreprocessor::new();
+        preprocessor.define("COUNT", "4");
+        let source = "#define SCALE 0.5\nlet x = COUNT * SCALE + COUNTER;\n#undef SCALE\nlet y = SCALE;";
+        assert_eq!(lines(&preprocessor.process_source("test", source).unwrap()), ["let x = 4 * 0.5 + COUNTER;", "let y = SCALE;"]);
+    }
+
+    #[test]
+    fn chunks_are_included_once() {
+        let preprocessor = with_chunks("include_once", &[
+            ("common", "fn common() {}"),
+            ("a", "#include \"common\"\nfn a() {}"),
+        ]);
+        let source = "#include \"a\"\n#include \"common\"\n#include <a>\nfn main() {}";
+        assert_eq!(lines(&preprocessor.process_source("test", source).unwrap()), ["fn common() {}", "fn a() {}", "fn main() {}"]);
+    }
+
+    #[test]
+    fn cyclic_includes_fail() {
+        let preprocessor = with_chunks("cyclic_include", &[
+            ("a", "#include \"b\""),
+            ("b", "#include \"a\""),
+        ]);
+        assert_eq!(preprocessor.process_source("test", "#include \"a\""), Err(ShaderError::CyclicInclude("a".to_string())));
+    }
+
+    #[test]
+    fn unknown_includes_report_their_line() {
+        let preprocessor = ShaderPreprocessor::new();
+        let result = preprocessor.process_source("test", "fn main() {}\n\n#include \"missing\"");
+        assert_eq!(result, Err(ShaderError::UnknownInclude { line: 3, chunk: "missing".to_string() }));
+        assert_eq!(result.unwrap_err().to_string(), "line 3: unknown chunk \"missing\"");
+    }
+
+    #[test]
+    fn unbalanced_conditionals_fail() {
+        let preprocessor = ShaderPreprocessor::new();
+        assert_eq!(preprocessor.process_source("test", "#ifdef A\n#endif\n#endif"), Err(ShaderError::UnexpectedEndif { line: 3 }));
+        assert_eq!(preprocessor.process_source("test", "#else"), Err(ShaderError::UnexpectedElse { line: 1 }));
+        assert_eq!(preprocessor.process_source("test", "#ifdef A\n#else\n#else\n#endif"), Err(ShaderError::UnexpectedElse { line: 3 }));
+        assert_eq!(preprocessor.process_source("test", "#ifdef A\nfn a() {}"), Err(ShaderError::UnterminatedIf { shader: "test".to_string() }));
+    }
+
+    #[test]
+    fn errors_in_chunks_report_the_line_of_the_chunk() {
+        let preprocessor = with_chunks("chunk_line", &[("broken", "fn broken() {}\n#endif")]);
+        assert_eq!(preprocessor.process_source("test", "\n\n#include \"broken\""), Err(ShaderError::UnexpectedEndif { line: 2 }));
+    }
+}
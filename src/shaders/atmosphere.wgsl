@@ -0,0 +1,115 @@
+// single scattering of the sun light in the atmosphere of a planet, see atmosphere.rs.
+// Outputs the light scattered towards the camera and, in alpha, the fraction of the light behind that reaches it
+
+#include "fullscreen"
+#include "uniforms"
+#include "depth"
+
+struct AtmosphereUniforms {
+    planet_center: vec3<f32>,
+    planet_radius: f32,
+    rayleigh_scattering: vec3<f32>,
+    atmosphere_radius: f32,
+    rayleigh_scale_height: f32,
+    mie_scattering: f32,
+    mie_scale_height: f32,
+    mie_anisotropy: f32,
+    sun_intensity: f32
+};
+@group(1) @binding(0) var<uniform> atmosphere: AtmosphereUniforms;
+
+// distances along the ray to the two intersections with the sphere, x > y when the ray misses it.
+// Written to stay precise for spheres much larger than the distance of the origin to their surface
+fn ray_sphere(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> vec2<f32> {
+    let offset = origin - atmosphere.planet_center;
+    let b = dot(offset, direction);
+    let distance = length(offset);
+    let c = (distance - radius) * (distance + radius);
+    let h = b * b - c;
+    if (h < 0.0) {
+        return vec2<f32>(1e30, -1e30);
+    }
+    let q = -(b + select(-1.0, 1.0, b >= 0.0) * sqrt(h));
+    if (abs(q) < 1e-6) {
+        return vec2<f32>(0.0, 0.0);
+    }
+    let t0 = q;
+    let t1 = c / q;
+    return vec2<f32>(min(t0, t1), max(t0, t1));
+}
+
+// relative densities of the air molecules and the aerosols at `position`
+fn density(position: vec3<f32>) -> vec2<f32> {
+    let altitude = max(length(position - atmosphere.planet_center) - atmosphere.planet_radius, 0.0);
+    return exp(-altitude / vec2<f32>(atmosphere.rayleigh_scale_height, atmosphere.mie_scale_height));
+}
+
+fn extinction(optical_depth: vec2<f32>) -> vec3<f32> {
+    return atmosphere.rayleigh_scattering * optical_depth.x + vec3<f32>(atmosphere.mie_scattering * 1.1 * optical_depth.y);
+}
+
+// optical depth from `position` to the sun, very large when the planet is in the way
+fn optical_depth_to_sun(position: vec3<f32>, sun_direction: vec3<f32>) -> vec2<f32> {
+    if (ray_sphere(position, sun_direction, atmosphere.planet_radius).x > 0.0) {
+        return vec2<f32>(1e10);
+    }
+    let ray_length = ray_sphere(position, sun_direction, atmosphere.atmosphere_radius).y;
+    let step = ray_length / 8.0;
+    var optical_depth = vec2<f32>(0.0);
+    for (var i = 0; i < 8; i = i + 1) {
+        optical_depth = optical_depth + density(position + sun_direction * (f32(i) + 0.5) * step) * step;
+    }
+    return optical_depth;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let depth = load_depth(in.position);
+    let world_position = depth_world_position(in.uv, depth);
+
+    let origin = camera.position.xyz;
+    let direction = normalize(world_position - origin);
+    let is_sky = depth >= 1.0;
+    var scene_distance = select(length(world_position - origin), 1e30, is_sky);
+
+    let atmosphere_hit = ray_sphere(origin, direction, atmosphere.atmosphere_radius);
+    let planet_hit = ray_sphere(origin, direction, atmosphere.planet_radius);
+    if (planet_hit.x <= planet_hit.y && planet_hit.x > 0.0) {
+        scene_distance = min(scene_distance, planet_hit.x);
+    }
+    let start = max(atmosphere_hit.x, 0.0);
+    let end = min(atmosphere_hit.y, scene_distance);
+    if (atmosphere_hit.x > atmosphere_hit.y || end <= start) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let sun_direction = normalize(lights.sun_direction);
+    let step = (end - start) / 16.0;
+    var optical_depth = vec2<f32>(0.0);
+    var rayleigh = vec3<f32>(0.0);
+    var mie = vec3<f32>(0.0);
+    for (var i = 0; i < 16; i = i + 1) {
+        let position = origin + direction * (start + (f32(i) + 0.5) * step);
+        let local_density = density(position) * step;
+        optical_depth = optical_depth + local_density;
+        let attenuation = exp(-extinction(optical_depth + optical_depth_to_sun(position, sun_direction)));
+        rayleigh = rayleigh + local_density.x * attenuation;
+        mie = mie + local_density.y * attenuation;
+    }
+
+    let mu = dot(direction, sun_direction);
+    let rayleigh_phase = 3.0 / (16.0 * 3.14159265) * (1.0 + mu * mu);
+    let g = atmosphere.mie_anisotropy;
+    let mie_phase = (1.0 - g * g) / (4.0 * 3.14159265 * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+
+    let sun_light = lights.sun_color * lights.sun_intensity * atmosphere.sun_intensity;
+    var scattered = sun_light * (rayleigh * atmosphere.rayleigh_scattering * rayleigh_phase + mie * atmosphere.mie_scattering * mie_phase);
+    let transmittance = exp(-extinction(optical_depth));
+
+    // disk of the sun, seen through the atmosphere
+    if (is_sky && !(planet_hit.x <= planet_hit.y && planet_hit.x > 0.0)) {
+        scattered = scattered + sun_light * transmittance * smoothstep(0.9997, 0.9999, mu);
+    }
+
+    return vec4<f32>(scattered, dot(transmittance, vec3<f32>(1.0 / 3.0)));
+}
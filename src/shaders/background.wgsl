@@ -0,0 +1,27 @@
+// gradient or skybox behind the meshes, see `Background`. Drawn first in the main pass without writing the depth
+
+#include "fullscreen"
+#include "uniforms"
+
+struct BackgroundUniforms {
+    top: vec3<f32>,
+    // 1 gradient, 2 skybox
+    mode: u32,
+    bottom: vec3<f32>,
+    intensity: f32
+};
+@group(1) @binding(0) var<uniform> background: BackgroundUniforms;
+@group(1) @binding(1) var skybox_texture: texture_cube<f32>;
+@group(1) @binding(2) var skybox_sampler: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // direction of the pixel, from a point on the far plane
+    let far = camera.inverse_view_proj * vec4<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w - camera.position.xyz);
+
+    if (background.mode == 1u) {
+        return vec4<f32>(mix(background.bottom, background.top, direction.y * 0.5 + 0.5), 1.0);
+    }
+    return vec4<f32>(textureSampleLevel(skybox_texture, skybox_sampler, direction, 0.0).rgb * background.intensity, 1.0);
+}
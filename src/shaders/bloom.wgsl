@@ -0,0 +1,62 @@
+// glow around the bright parts of the HDR colors, see `Bloom`. The bright colors are downsampled into a chain of
+// textures of half the size of the previous one, defining BLOOM_PREFILTER for the first, then upsampled back and
+// added to each larger texture with BLOOM_UPSAMPLE, the last upsample adding the glow to the scene
+
+#include "post_process"
+
+struct BloomUniforms {
+    threshold: f32,
+    knee: f32,
+    radius: f32
+};
+@group(1) @binding(0) var<uniform> bloom: BloomUniforms;
+
+#ifdef BLOOM_UPSAMPLE
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // 3x3 tent filter, wider with the radius
+    let offset = input_texel_size() * bloom.radius;
+    var color = input_color(in.uv).rgb * 4.0;
+    color = color + (input_color(in.uv + vec2<f32>(-offset.x, 0.0)).rgb + input_color(in.uv + vec2<f32>(offset.x, 0.0)).rgb) * 2.0;
+    color = color + (input_color(in.uv + vec2<f32>(0.0, -offset.y)).rgb + input_color(in.uv + vec2<f32>(0.0, offset.y)).rgb) * 2.0;
+    color = color + input_color(in.uv - offset).rgb + input_color(in.uv + offset).rgb;
+    color = color + input_color(in.uv + vec2<f32>(-offset.x, offset.y)).rgb + input_color(in.uv + vec2<f32>(offset.x, -offset.y)).rgb;
+    return vec4<f32>(color / 16.0, 1.0);
+}
+#else
+// keeps the part of the color over the threshold, with a smooth transition of `knee` below it
+fn bright_part(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    let soft_contribution = soft * soft / (4.0 * bloom.knee + 0.0001);
+    return color * max(soft_contribution, brightness - bloom.threshold) / max(brightness, 0.0001);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // 13 taps downsample, weighting overlapping 4x4 boxes to avoid flickering
+    let texel = input_texel_size();
+    let a = input_color(in.uv + texel * vec2<f32>(-2.0, -2.0)).rgb;
+    let b = input_color(in.uv + texel * vec2<f32>(0.0, -2.0)).rgb;
+    let c = input_color(in.uv + texel * vec2<f32>(2.0, -2.0)).rgb;
+    let d = input_color(in.uv + texel * vec2<f32>(-2.0, 0.0)).rgb;
+    let e = input_color(in.uv).rgb;
+    let f = input_color(in.uv + texel * vec2<f32>(2.0, 0.0)).rgb;
+    let g = input_color(in.uv + texel * vec2<f32>(-2.0, 2.0)).rgb;
+    let h = input_color(in.uv + texel * vec2<f32>(0.0, 2.0)).rgb;
+    let i = input_color(in.uv + texel * vec2<f32>(2.0, 2.0)).rgb;
+    let j = input_color(in.uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    let k = input_color(in.uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+    let l = input_color(in.uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    let m = input_color(in.uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+
+    var color = e * 0.125 + (j + k + l + m) * 0.125;
+    color = color + (a + c + g + i) * 0.03125;
+    color = color + (b + d + f + h) * 0.0625;
+#ifdef BLOOM_PREFILTER
+    // the sky or the sun can be very bright, which would turn single pixels into large squares
+    color = bright_part(min(max(color, vec3<f32>(0.0)), vec3<f32>(1000.0)));
+#endif
+    return vec4<f32>(color, 1.0);
+}
+#endif
@@ -0,0 +1,24 @@
+// depth buffer of the main pass read by fullscreen passes, see `depth_pass_bind_group_layout`,
+// or by materials defining READS_SCENE_DEPTH at group 3, see `Material::reads_scene_depth`.
+// Bound as a float texture, the GL backend cannot load from depth textures
+
+#ifdef READS_SCENE_DEPTH
+@group(3) @binding(0) var depth_texture: texture_2d<f32>;
+#else
+@group(1) @binding(1) var depth_texture: texture_2d<f32>;
+#endif
+
+fn load_depth(frag_position: vec4<f32>) -> f32 {
+    return textureLoad(depth_texture, vec2<i32>(frag_position.xy), 0).x;
+}
+
+// position in [0, 1] on the screen of a fragment, (0, 0) being the top left corner
+fn screen_uv(frag_position: vec4<f32>) -> vec2<f32> {
+    return frag_position.xy / vec2<f32>(textureDimensions(depth_texture));
+}
+
+// world space position of the surface seen at `uv`, from its depth and the camera uniforms
+fn depth_world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = camera.inverse_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return world.xyz / world.w;
+}
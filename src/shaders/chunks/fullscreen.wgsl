@@ -0,0 +1,16 @@
+// vertex shader of fullscreen passes: a single triangle covering the screen, drawn with `draw(0..3, 0..1)`
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    // (0, 0) at the top left corner of the screen
+    @location(0) uv: vec2<f32>
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> FullscreenOutput {
+    var output: FullscreenOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    output.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    output.uv = uv;
+    return output;
+}
@@ -0,0 +1,6 @@
+// lighting helpers
+
+// Lambertian diffuse term, clamped to `ambient` so unlit faces are not pitch black
+fn lambert(normal: vec3<f32>, light_dir: vec3<f32>, ambient: f32) -> f32 {
+    return max(dot(normalize(normal), normalize(light_dir)), ambient);
+}
@@ -0,0 +1,21 @@
+// normal mapping, enabled by NORMAL_MAP. OBJECT_SPACE_NORMAL_MAP selects object space maps instead of tangent space ones
+
+#include "vertex"
+
+#ifdef NORMAL_MAP
+// world space normal from a normal map texel `map_sample` (in [0, 1]), blended with the mesh normal by `strength`.
+// Tangent space maps need the HAS_TANGENT attribute
+fn apply_normal_map(in: VertexOutput, map_sample: vec3<f32>, strength: f32) -> vec3<f32> {
+    let normal = normalize(in.vWorldNormal);
+    let map_normal = map_sample * 2.0 - 1.0;
+#ifdef OBJECT_SPACE_NORMAL_MAP
+    let object_normal = normalize((mesh.normal_matrix * vec4<f32>(map_normal, 0.0)).xyz);
+    return normalize(mix(normal, object_normal, strength));
+#else
+    let tangent = normalize(in.vWorldTangent.xyz - normal * dot(in.vWorldTangent.xyz, normal));
+    let bitangent = cross(normal, tangent) * in.vWorldTangent.w;
+    let detail = vec3<f32>(map_normal.xy * strength, map_normal.z);
+    return normalize(tangent * detail.x + bitangent * detail.y + normal * detail.z);
+#endif
+}
+#endif
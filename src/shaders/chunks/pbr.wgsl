@@ -0,0 +1,69 @@
+// metallic-roughness physically based shading with the scene lights, see light.rs
+
+#include "uniforms"
+#include "shadows"
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (3.14159265 * d * d);
+}
+
+// Smith geometry term with the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Fresnel for ambient light, rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// light coming from `direction`: the hemisphere light plus the environment map,
+// sampled in a lower mip level for rougher surfaces
+fn ambient_light(direction: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let hemisphere = mix(lights.ground_color, lights.sky_color, direction.y * 0.5 + 0.5) * lights.ambient_intensity;
+
+    let uv = vec2<f32>(atan2(direction.z, direction.x) / 6.28318531 + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / 3.14159265);
+    let level = roughness * max(lights.environment_mip_count - 1.0, 0.0);
+    let environment = textureSampleLevel(environment_texture, environment_sampler, uv, level).rgb * lights.environment_intensity;
+
+    return hemisphere + environment;
+}
+
+fn pbr_shade(albedo: vec3<f32>, metallic: f32, roughness: f32, normal: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let roughness = clamp(roughness, 0.04, 1.0);
+    let n = normalize(normal);
+    let v = normalize(camera.position.xyz - world_position);
+    let l = normalize(lights.sun_direction);
+    let h = normalize(v + l);
+
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_h = max(dot(n, h), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+
+    // direct light of the sun
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * f / (4.0 * n_dot_v * n_dot_l + 0.0001);
+    let k_diffuse = (1.0 - f) * (1.0 - metallic);
+    let direct = (k_diffuse * albedo / 3.14159265 + specular) * lights.sun_color * lights.sun_intensity * n_dot_l * shadow_factor(world_position, n);
+
+    // ambient light
+    let f_ambient = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_diffuse_ambient = (1.0 - f_ambient) * (1.0 - metallic);
+    let diffuse_ambient = k_diffuse_ambient * albedo * ambient_light(n, 1.0);
+    let specular_ambient = f_ambient * ambient_light(reflect(-v, n), roughness);
+
+    return direct + diffuse_ambient + specular_ambient;
+}
@@ -0,0 +1,22 @@
+// bindings of the post-process passes, see post_process.rs: the uniforms of the pass at group 1, binding 0,
+// the depth buffer at binding 1, see the `depth` chunk, and the output of the previous pass at bindings 2 and 3
+
+#include "fullscreen"
+#include "uniforms"
+#include "depth"
+
+@group(1) @binding(2) var input_texture: texture_2d<f32>;
+@group(1) @binding(3) var input_sampler: sampler;
+
+fn input_color(uv: vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(input_texture, input_sampler, uv, 0.0);
+}
+
+// size of a pixel in uv space
+fn input_texel_size() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(input_texture));
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
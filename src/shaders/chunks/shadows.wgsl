@@ -0,0 +1,53 @@
+// cascaded shadow maps of the sun, see shadow.rs
+
+#include "uniforms"
+
+struct ShadowUniforms {
+    view_proj: array<mat4x4<f32>, 4>,
+    split_depths: vec4<f32>,
+    texel_sizes: vec4<f32>,
+    camera_forward: vec3<f32>,
+    cascade_count: u32,
+    normal_bias: f32,
+    pcf_radius: i32
+};
+@group(0) @binding(4) var<uniform> shadows: ShadowUniforms;
+@group(0) @binding(5) var shadow_map: texture_depth_2d_array;
+@group(0) @binding(6) var shadow_sampler: sampler_comparison;
+
+// fraction of the sun light reaching `world_position`, filtered with PCF
+fn shadow_factor(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let view_depth = dot(world_position - camera.position.xyz, shadows.camera_forward);
+    if (shadows.cascade_count == 0u || view_depth > shadows.split_depths[shadows.cascade_count - 1u]) {
+        return 1.0;
+    }
+
+    var cascade = 0u;
+    loop {
+        if (cascade + 1u >= shadows.cascade_count || view_depth < shadows.split_depths[cascade]) {
+            break;
+        }
+        cascade = cascade + 1u;
+    }
+
+    // pushed along the normal to avoid shadow acne on surfaces facing away from the sun
+    let biased_position = world_position + normalize(normal) * shadows.normal_bias * shadows.texel_sizes[cascade];
+    let clip = shadows.view_proj[cascade] * vec4<f32>(biased_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var lit = 0.0;
+    var samples = 0.0;
+    for (var x = -shadows.pcf_radius; x <= shadows.pcf_radius; x = x + 1) {
+        for (var y = -shadows.pcf_radius; y <= shadows.pcf_radius; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, i32(cascade), ndc.z);
+            samples = samples + 1.0;
+        }
+    }
+    return lit / samples;
+}
@@ -0,0 +1,14 @@
+// triplanar mapping: a texture projected on the yz, xz and xy planes, blended by the normal
+
+// blend weights of the three projections, a higher sharpness narrows the transitions
+fn triplanar_weights(normal: vec3<f32>, sharpness: f32) -> vec3<f32> {
+    let weights = pow(abs(normalize(normal)), vec3<f32>(sharpness));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+fn triplanar_sample(t: texture_2d<f32>, s: sampler, position: vec3<f32>, weights: vec3<f32>, scale: f32) -> vec4<f32> {
+    let x_projection = textureSample(t, s, position.yz * scale);
+    let y_projection = textureSample(t, s, position.xz * scale);
+    let z_projection = textureSample(t, s, position.xy * scale);
+    return x_projection * weights.x + y_projection * weights.y + z_projection * weights.z;
+}
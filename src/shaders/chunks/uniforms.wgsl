@@ -0,0 +1,36 @@
+// uniforms shared by every pipeline, see uniforms.rs
+
+struct CameraUniforms {
+    view_proj: mat4x4<f32>,
+    inverse_view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    near: f32,
+    far: f32,
+    // seconds since the scene was created
+    time: f32
+};
+@group(0) @binding(0) var<uniform> camera: CameraUniforms;
+
+struct LightUniforms {
+    sun_direction: vec3<f32>,
+    sun_intensity: f32,
+    sun_color: vec3<f32>,
+    ambient_intensity: f32,
+    sky_color: vec3<f32>,
+    environment_intensity: f32,
+    ground_color: vec3<f32>,
+    environment_mip_count: f32
+};
+@group(0) @binding(1) var<uniform> lights: LightUniforms;
+
+// equirectangular environment map, black when the scene has none
+@group(0) @binding(2) var environment_texture: texture_2d<f32>;
+@group(0) @binding(3) var environment_sampler: sampler;
+
+struct MeshUniforms {
+    world: mat4x4<f32>,
+    normal_matrix: mat4x4<f32>,
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>
+};
+@group(1) @binding(0) var<uniform> mesh: MeshUniforms;
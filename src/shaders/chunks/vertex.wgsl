@@ -0,0 +1,80 @@
+// vertex shader shared by the built-in materials
+// HAS_UV and HAS_TANGENT are defined by materials reading those attributes, see `vertex.rs` for the locations.
+// INSTANCED is defined for the pipelines of instanced meshes, whose instances are placed relative to the mesh
+
+#include "uniforms"
+
+struct VertexInput {
+    @location(0) pos: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+#ifdef HAS_UV
+    @location(3) uv: vec2<f32>,
+#endif
+#ifdef HAS_TANGENT
+    @location(4) tangent: vec4<f32>,
+#endif
+};
+
+#ifdef INSTANCED
+struct InstanceInput {
+    @location(11) world_0: vec4<f32>,
+    @location(12) world_1: vec4<f32>,
+    @location(13) world_2: vec4<f32>,
+    @location(14) world_3: vec4<f32>,
+    @location(15) color: vec4<f32>,
+};
+
+// normals are transformed by the cofactor matrix, the inverse transpose up to a positive scale
+fn instance_normal(world: mat4x4<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let x = world[0].xyz;
+    let y = world[1].xyz;
+    let z = world[2].xyz;
+    return mat3x3<f32>(cross(y, z), cross(z, x), cross(x, y)) * normal * sign(dot(x, cross(y, z)));
+}
+#endif
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) vPosition: vec3<f32>,
+    @location(1) vColor: vec4<f32>,
+    @location(2) vNormal: vec3<f32>,
+    @location(3) vWorldPosition: vec3<f32>,
+    @location(4) vWorldNormal: vec3<f32>,
+#ifdef HAS_UV
+    @location(5) vUv: vec2<f32>,
+#endif
+#ifdef HAS_TANGENT
+    @location(6) vWorldTangent: vec4<f32>,
+#endif
+};
+
+@vertex
+#ifdef INSTANCED
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let instance_world = mat4x4<f32>(instance.world_0, instance.world_1, instance.world_2, instance.world_3);
+    let world = mesh.world * instance_world;
+    let normal = instance_normal(instance_world, in.normal);
+    let color = in.color * instance.color.rgb;
+#else
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let world = mesh.world;
+    let normal = in.normal;
+    let color = in.color;
+#endif
+    var output: VertexOutput;
+    let world_position = world * vec4<f32>(in.pos, 1.0);
+    output.position = camera.view_proj * world_position;
+    output.vPosition = in.pos;
+    output.vColor = vec4<f32>(color, 1.0);
+    output.vNormal = in.normal;
+    output.vWorldPosition = world_position.xyz;
+    output.vWorldNormal = (mesh.normal_matrix * vec4<f32>(normal, 0.0)).xyz;
+#ifdef HAS_UV
+    output.vUv = in.uv;
+#endif
+#ifdef HAS_TANGENT
+    output.vWorldTangent = vec4<f32>((world * vec4<f32>(in.tangent.xyz, 0.0)).xyz, in.tangent.w);
+#endif
+    return output;
+}
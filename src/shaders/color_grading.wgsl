@@ -0,0 +1,19 @@
+// contrast, saturation and tint of the linear HDR colors, see `ColorGrading`
+
+#include "post_process"
+
+struct ColorGradingUniforms {
+    tint: vec3<f32>,
+    contrast: f32,
+    saturation: f32
+};
+@group(1) @binding(0) var<uniform> grading: ColorGradingUniforms;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var color = max(input_color(in.uv).rgb, vec3<f32>(0.0));
+    // contrast around middle gray, in log space so that it works for HDR colors
+    color = 0.18 * pow(color / 0.18, vec3<f32>(grading.contrast));
+    color = mix(vec3<f32>(luminance(color)), color, grading.saturation);
+    return vec4<f32>(max(color, vec3<f32>(0.0)) * grading.tint, 1.0);
+}
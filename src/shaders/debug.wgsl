@@ -0,0 +1,45 @@
+// debug render modes, exactly one RENDER_MODE_* flag is expected to be defined
+
+#include "vertex"
+
+fn heat_map(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0);
+    return clamp(vec3<f32>(2.0 * x - 0.5, 1.5 - abs(2.0 * x - 1.0) * 1.5, 1.5 - 2.0 * x), vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef RENDER_MODE_WIREFRAME
+    let color = vec3<f32>(0.9, 0.9, 0.9);
+#endif
+#ifdef RENDER_MODE_WIREFRAME_OVERLAY
+    let color = vec3<f32>(0.05, 0.05, 0.05);
+#endif
+#ifdef RENDER_MODE_NORMALS
+    let color = normalize(in.vWorldNormal) * 0.5 + 0.5;
+#endif
+#ifdef RENDER_MODE_HEIGHT
+    let extent = max(mesh.bounds_max.y - mesh.bounds_min.y, 0.0001);
+    let color = heat_map((in.vPosition.y - mesh.bounds_min.y) / extent);
+#endif
+#ifdef RENDER_MODE_SLOPE
+    let color = heat_map(1.0 - abs(dot(normalize(in.vWorldNormal), vec3<f32>(0.0, 1.0, 0.0))));
+#endif
+#ifdef RENDER_MODE_CHECKER
+#ifdef HAS_UV
+    let cells = floor(in.vUv * 16.0);
+    let parity = (i32(cells.x) + i32(cells.y)) & 1;
+#else
+    let extent = mesh.bounds_max.xyz - mesh.bounds_min.xyz;
+    let cells = floor(in.vPosition * 16.0 / max(max(extent.x, extent.y), max(extent.z, 0.0001)));
+    let parity = (i32(cells.x) + i32(cells.y) + i32(cells.z)) & 1;
+#endif
+    let color = mix(vec3<f32>(0.1, 0.1, 0.1), vec3<f32>(0.9, 0.9, 0.9), f32(parity));
+#endif
+#ifdef RENDER_MODE_DEPTH
+    let depth = (distance(in.vWorldPosition, camera.position.xyz) - camera.near) / (camera.far - camera.near);
+    let color = vec3<f32>(1.0, 1.0, 1.0) * (1.0 - clamp(depth, 0.0, 1.0));
+#endif
+
+    return vec4<f32>(color, 1.0);
+}
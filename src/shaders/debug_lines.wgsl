@@ -0,0 +1,26 @@
+// world space lines and points of the debug renderer
+
+#include "uniforms"
+
+struct DebugVertexInput {
+    @location(0) pos: vec3<f32>,
+    @location(1) color: vec4<f32>
+};
+
+struct DebugVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) vColor: vec4<f32>
+};
+
+@vertex
+fn vs_main(in: DebugVertexInput) -> DebugVertexOutput {
+    var output: DebugVertexOutput;
+    output.position = camera.view_proj * vec4<f32>(in.pos, 1.0);
+    output.vColor = in.color;
+    return output;
+}
+
+@fragment
+fn fs_main(in: DebugVertexOutput) -> @location(0) vec4<f32> {
+    return in.vColor;
+}
@@ -0,0 +1,24 @@
+// default shader, colored by the normals or with TEXTURED by a texture sampled with the mesh uvs
+
+#include "vertex"
+#include "lighting"
+
+#ifdef TEXTURED
+@group(2) @binding(1) var base_texture: texture_2d<f32>;
+@group(2) @binding(2) var base_sampler: sampler;
+#endif
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef TEXTURED
+    let albedo: vec3<f32> = textureSample(base_texture, base_sampler, in.vUv).rgb;
+#else
+    let albedo: vec3<f32> = in.vNormal;
+#endif
+
+    let ndl: f32 = lambert(in.vWorldNormal, lights.sun_direction, 0.02);
+
+    let color: vec3<f32> = ndl * albedo; //in.vColor.xyz * ndl;
+
+    return vec4(color, 1.0);
+}
@@ -0,0 +1,17 @@
+// copies the closest sample of the multisampled depth buffer of the main pass into a single sampled one,
+// read by the passes drawn after it. Bound as a float texture, the GL backend cannot load from depth textures.
+// SAMPLE_COUNT is defined by the engine, textureNumSamples is not available on GL
+
+#include "fullscreen"
+
+@group(0) @binding(0) var multisampled_depth: texture_multisampled_2d<f32>;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @builtin(frag_depth) f32 {
+    let position = vec2<i32>(in.position.xy);
+    var depth = 1.0;
+    for (var i = 0; i < SAMPLE_COUNT; i = i + 1) {
+        depth = min(depth, textureLoad(multisampled_depth, position, i).x);
+    }
+    return depth;
+}
@@ -0,0 +1,56 @@
+// distance fog over the meshes of the main pass, see fog.rs.
+// Outputs the fog color with its opacity in alpha, blended over the scene
+
+#include "fullscreen"
+#include "uniforms"
+#include "depth"
+
+struct FogUniforms {
+    color: vec3<f32>,
+    // 0 linear, 1 exponential, 2 height-based
+    mode: u32,
+    start: f32,
+    end: f32,
+    density: f32,
+    height_falloff: f32,
+    base_height: f32,
+    max_opacity: f32
+};
+@group(1) @binding(0) var<uniform> fog: FogUniforms;
+
+// optical depth along the segment from the camera to `position` of a fog whose density
+// decreases exponentially above `base_height`
+fn height_fog_depth(origin: vec3<f32>, position: vec3<f32>) -> f32 {
+    let distance = length(position - origin);
+    let origin_density = fog.density * exp(-fog.height_falloff * (origin.y - fog.base_height));
+    let falloff = fog.height_falloff * (position.y - origin.y);
+    // average of the density along the segment, the limit being 1 for horizontal segments
+    var average = 1.0;
+    if (abs(falloff) > 0.0001) {
+        average = (1.0 - exp(-falloff)) / falloff;
+    }
+    return origin_density * distance * average;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let depth = load_depth(in.position);
+    // the background is left to the clear color or the atmosphere
+    if (depth >= 1.0) {
+        return vec4<f32>(fog.color, 0.0);
+    }
+    let origin = camera.position.xyz;
+    let position = depth_world_position(in.uv, depth);
+    let distance = length(position - origin);
+
+    var opacity = 0.0;
+    if (fog.mode == 0u) {
+        opacity = clamp((distance - fog.start) / max(fog.end - fog.start, 0.0001), 0.0, 1.0);
+    } else if (fog.mode == 1u) {
+        opacity = 1.0 - exp(-fog.density * max(distance - fog.start, 0.0));
+    } else {
+        opacity = 1.0 - exp(-height_fog_depth(origin, position));
+    }
+
+    return vec4<f32>(fog.color, min(opacity, fog.max_opacity));
+}
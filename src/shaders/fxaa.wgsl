@@ -0,0 +1,37 @@
+// fast approximate anti-aliasing, blurring along the edges found from the luminance of the neighbours.
+// Expects gamma corrected colors, so it goes after the tone mapping
+
+#include "post_process"
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let span_max = 8.0;
+    let reduce_min = 1.0 / 128.0;
+    let reduce_mul = 1.0 / 8.0;
+    let texel = input_texel_size();
+
+    let center = input_color(in.uv);
+    let luma_nw = luminance(input_color(in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luminance(input_color(in.uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luminance(input_color(in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luminance(input_color(in.uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let luma_m = luminance(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // direction along the edge
+    var direction = vec2<f32>(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    let direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    let inverse_direction_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * inverse_direction_min, vec2<f32>(-span_max), vec2<f32>(span_max)) * texel;
+
+    let color_a = 0.5 * (input_color(in.uv + direction * (1.0 / 3.0 - 0.5)).rgb + input_color(in.uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    let color_b = color_a * 0.5 + 0.25 * (input_color(in.uv - direction * 0.5).rgb + input_color(in.uv + direction * 0.5).rgb);
+    let luma_b = luminance(color_b);
+
+    // the wider blur went past the edge
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(color_a, center.a);
+    }
+    return vec4<f32>(color_b, center.a);
+}
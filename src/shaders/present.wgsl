@@ -0,0 +1,21 @@
+// copies the output of the post-process chain to the surface.
+// SRGB_OUTPUT is defined for sRGB surfaces, which encode what they receive: the display colors are decoded first.
+// Without it, also copies the viewports, already in the format of the surface, into their region of it
+
+#include "post_process"
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = clamp(input_color(in.uv).rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+#ifdef SRGB_OUTPUT
+    return vec4<f32>(srgb_to_linear(color), 1.0);
+#else
+    return vec4<f32>(color, 1.0);
+#endif
+}
@@ -0,0 +1,22 @@
+// depth only pass rendering the meshes into a shadow map cascade, whose matrix replaces the camera of the scene,
+// or writing the depth of transparent meshes into the depth buffer of the main pass. INSTANCED for instanced meshes
+
+#include "uniforms"
+
+#ifdef INSTANCED
+@vertex
+fn vs_main(
+    @location(0) pos: vec3<f32>,
+    @location(11) world_0: vec4<f32>,
+    @location(12) world_1: vec4<f32>,
+    @location(13) world_2: vec4<f32>,
+    @location(14) world_3: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * mesh.world * mat4x4<f32>(world_0, world_1, world_2, world_3) * vec4<f32>(pos, 1.0);
+}
+#else
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * mesh.world * vec4<f32>(pos, 1.0);
+}
+#endif
@@ -0,0 +1,104 @@
+// screen space ambient occlusion, see `AmbientOcclusion`: darkens the creases by counting the points of a hemisphere
+// around each surface that lie behind the depth buffer. The occlusion is written to a single channel texture,
+// then SSAO_COMPOSITE blurs it and multiplies the scene colors with it
+
+#include "post_process"
+
+struct AmbientOcclusionUniforms {
+    radius: f32,
+    intensity: f32,
+    bias: f32
+};
+@group(1) @binding(0) var<uniform> occlusion: AmbientOcclusionUniforms;
+
+#ifdef SSAO_COMPOSITE
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // 4x4 box blur, hiding the 4x4 pattern of the sample rotations
+    let texel = input_texel_size();
+    var sum = 0.0;
+    for (var y = -2; y < 2; y = y + 1) {
+        for (var x = -2; x < 2; x = x + 1) {
+            sum = sum + input_color(in.uv + (vec2<f32>(f32(x), f32(y)) + 0.5) * texel).r;
+        }
+    }
+    return vec4<f32>(vec3<f32>(sum / 16.0), 1.0);
+}
+#else
+fn world_position_at(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(depth_texture));
+    let clamped = clamp(pixel, vec2<i32>(0), size - 1);
+    let depth = textureLoad(depth_texture, clamped, 0).x;
+    return depth_world_position((vec2<f32>(clamped) + 0.5) / vec2<f32>(size), depth);
+}
+
+// normal of the surface from the depth of the neighbours, taking the closest one on each axis to keep the edges sharp
+fn depth_normal(pixel: vec2<i32>, position: vec3<f32>) -> vec3<f32> {
+    let left = position - world_position_at(pixel - vec2<i32>(1, 0));
+    let right = world_position_at(pixel + vec2<i32>(1, 0)) - position;
+    let up = position - world_position_at(pixel - vec2<i32>(0, 1));
+    let down = world_position_at(pixel + vec2<i32>(0, 1)) - position;
+    let dx = select(left, right, length(right) < length(left));
+    let dy = select(up, down, length(down) < length(up));
+    let normal = normalize(cross(dx, dy));
+    return select(-normal, normal, dot(normal, camera.position.xyz - position) > 0.0);
+}
+
+fn hash(n: f32) -> f32 {
+    return fract(sin(n * 12.9898) * 43758.5453);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let sample_count = 16;
+    let depth = load_depth(in.position);
+    if (depth >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let pixel = vec2<i32>(in.position.xy);
+    let uv = screen_uv(in.position);
+    let position = depth_world_position(uv, depth);
+    let normal = depth_normal(pixel, position);
+    let distance_to_camera = distance(camera.position.xyz, position);
+
+    // the hemisphere is rotated differently in each pixel of a 4x4 tile, the composite blur averages them
+    let tile = vec2<f32>(pixel % vec2<i32>(4));
+    let rotation = hash(tile.x * 4.0 + tile.y + 1.0) * 6.2831853;
+    var helper = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.9) {
+        helper = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(helper, normal));
+    let bitangent = cross(normal, tangent);
+
+    var occluded = 0.0;
+    for (var i = 0; i < sample_count; i = i + 1) {
+        let cos_theta = hash(f32(i) + 0.5);
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let phi = hash(f32(i) + 17.5) * 6.2831853 + rotation;
+        let direction = (tangent * cos(phi) + bitangent * sin(phi)) * sin_theta + normal * cos_theta;
+        // more samples close to the surface
+        let scale = (f32(i) + 1.0) / f32(sample_count);
+        let sample_position = position + direction * occlusion.radius * mix(0.1, 1.0, scale * scale);
+
+        let clip = camera.view_proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xyz / clip.w;
+        let sample_uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (clip.w <= 0.0 || any(sample_uv < vec2<f32>(0.0)) || any(sample_uv > vec2<f32>(1.0))) {
+            continue;
+        }
+
+        let sample_pixel = vec2<i32>(sample_uv * vec2<f32>(textureDimensions(depth_texture)));
+        let surface_distance = distance(camera.position.xyz, world_position_at(sample_pixel));
+        // surfaces much closer to the camera than the sampled one do not occlude it
+        let range = smoothstep(0.0, 1.0, occlusion.radius / max(abs(distance_to_camera - surface_distance), 0.0001));
+        if (surface_distance < distance(camera.position.xyz, sample_position) - occlusion.bias) {
+            occluded = occluded + range;
+        }
+    }
+
+    let visibility = clamp(1.0 - occluded / f32(sample_count) * occlusion.intensity, 0.0, 1.0);
+    return vec4<f32>(vec3<f32>(visibility), 1.0);
+}
+#endif
@@ -0,0 +1,48 @@
+// standard physically based material with a base color, metallic and roughness.
+// BASE_COLOR_TEXTURE multiplies the base color by a texture sampled with the mesh uvs and NORMAL_MAP perturbs the lighting normals
+
+#include "vertex"
+#include "normal_mapping"
+#include "pbr"
+
+struct StandardUniforms {
+    base_color: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_map_strength: f32,
+    normal_map_scale: f32
+};
+@group(2) @binding(0) var<uniform> frag_uniforms : StandardUniforms;
+
+#ifdef BASE_COLOR_TEXTURE
+@group(2) @binding(1) var base_color_texture: texture_2d<f32>;
+@group(2) @binding(2) var base_color_sampler: sampler;
+#endif
+
+#ifdef NORMAL_MAP
+#ifdef BASE_COLOR_TEXTURE
+@group(2) @binding(3) var normal_texture: texture_2d<f32>;
+@group(2) @binding(4) var normal_sampler: sampler;
+#else
+@group(2) @binding(1) var normal_texture: texture_2d<f32>;
+@group(2) @binding(2) var normal_sampler: sampler;
+#endif
+#endif
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef BASE_COLOR_TEXTURE
+    let albedo = frag_uniforms.base_color.rgb * textureSample(base_color_texture, base_color_sampler, in.vUv).rgb;
+#else
+    let albedo = frag_uniforms.base_color.rgb;
+#endif
+
+#ifdef NORMAL_MAP
+    let map_sample = textureSample(normal_texture, normal_sampler, in.vUv * frag_uniforms.normal_map_scale).xyz;
+    let normal = apply_normal_map(in, map_sample, frag_uniforms.normal_map_strength);
+#else
+    let normal = normalize(in.vWorldNormal);
+#endif
+
+    return vec4(pbr_shade(albedo, frag_uniforms.metallic, frag_uniforms.roughness, normal, in.vWorldPosition), 1.0);
+}
@@ -0,0 +1,93 @@
+// terrain shader, SPHERE_TERRAIN switches from a flat terrain along the y axis to a spherical one
+// and TEXTURED replaces the flat colors with grass, rock and snow textures, projected on the xz plane or with TRIPLANAR on the three axes.
+// NORMAL_MAP perturbs the lighting normals with a tangent space map, or an object space one with OBJECT_SPACE_NORMAL_MAP
+
+#include "vertex"
+#include "triplanar"
+#include "normal_mapping"
+#include "pbr"
+
+struct FragUniforms {
+    // of the grass, rock and snow layers
+    roughness: vec3<f32>,
+    max_height: f32,
+    metallic: vec3<f32>,
+    sphere_radius: f32,
+    texture_scale: f32,
+    triplanar_sharpness: f32,
+    normal_map_strength: f32,
+    normal_map_scale: f32
+};
+@group(2) @binding(0) var<uniform> frag_uniforms : FragUniforms;
+
+#ifdef TEXTURED
+@group(2) @binding(1) var grass_texture: texture_2d<f32>;
+@group(2) @binding(2) var grass_sampler: sampler;
+@group(2) @binding(3) var rock_texture: texture_2d<f32>;
+@group(2) @binding(4) var rock_sampler: sampler;
+@group(2) @binding(5) var snow_texture: texture_2d<f32>;
+@group(2) @binding(6) var snow_sampler: sampler;
+#endif
+
+#ifdef NORMAL_MAP
+#ifdef TEXTURED
+@group(2) @binding(7) var normal_texture: texture_2d<f32>;
+@group(2) @binding(8) var normal_sampler: sampler;
+#else
+@group(2) @binding(1) var normal_texture: texture_2d<f32>;
+@group(2) @binding(2) var normal_sampler: sampler;
+#endif
+#endif
+
+fn lighting_normal(in: VertexOutput) -> vec3<f32> {
+#ifdef NORMAL_MAP
+    let map_sample = textureSample(normal_texture, normal_sampler, in.vUv * frag_uniforms.normal_map_scale).xyz;
+    return apply_normal_map(in, map_sample, frag_uniforms.normal_map_strength);
+#else
+    return normalize(in.vWorldNormal);
+#endif
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+#ifdef SPHERE_TERRAIN
+    let height01 = (length(in.vPosition) - frag_uniforms.sphere_radius) / frag_uniforms.max_height;
+    let up = normalize(in.vPosition);
+#else
+    let height01 = in.vPosition.y / frag_uniforms.max_height;
+    let up = vec3(0.0, 1.0, 0.0);
+#endif
+
+#ifdef TRIPLANAR
+    let weights = triplanar_weights(in.vNormal, frag_uniforms.triplanar_sharpness);
+    let grass_color = triplanar_sample(grass_texture, grass_sampler, in.vPosition, weights, frag_uniforms.texture_scale).rgb;
+    let snow_color = triplanar_sample(snow_texture, snow_sampler, in.vPosition, weights, frag_uniforms.texture_scale).rgb;
+    let slope_color = triplanar_sample(rock_texture, rock_sampler, in.vPosition, weights, frag_uniforms.texture_scale).rgb;
+#else
+#ifdef TEXTURED
+    let uv = in.vPosition.xz * frag_uniforms.texture_scale;
+    let grass_color = textureSample(grass_texture, grass_sampler, uv).rgb;
+    let snow_color = textureSample(snow_texture, snow_sampler, uv).rgb;
+    let slope_color = textureSample(rock_texture, rock_sampler, uv).rgb;
+#else
+    let grass_color = vec3(0.0, 0.5, 0.0);
+    let snow_color = vec3(1.0, 1.0, 1.0);
+    let slope_color = vec3(0.2, 0.1, 0.1);
+#endif
+#endif
+
+    let snow_factor = smoothstep(SNOW_START, SNOW_START + 0.1, height01);
+    let slope = 1.0 - pow(dot(normalize(in.vNormal), up), 32.0);
+    let slope_factor = smoothstep(0.8, 0.9, slope);
+
+    let flat_color = mix(grass_color, snow_color, snow_factor);
+    let color: vec3<f32> = mix(flat_color, slope_color, slope_factor);
+
+    // roughness and metallic of the layers, blended like the colors
+    let grass_surface = vec2<f32>(frag_uniforms.roughness.x, frag_uniforms.metallic.x);
+    let rock_surface = vec2<f32>(frag_uniforms.roughness.y, frag_uniforms.metallic.y);
+    let snow_surface = vec2<f32>(frag_uniforms.roughness.z, frag_uniforms.metallic.z);
+    let surface = mix(mix(grass_surface, snow_surface, snow_factor), rock_surface, slope_factor);
+
+    return vec4(pbr_shade(color, surface.y, surface.x, lighting_normal(in), in.vWorldPosition), 1.0);
+}
@@ -0,0 +1,25 @@
+// exposure, tone mapping and gamma correction of the HDR colors of the scene, see `ToneMapping`
+
+#include "post_process"
+#include "tonemapping"
+
+struct ToneMappingUniforms {
+    // 0 none, 1 Reinhard, 2 ACES
+    curve: u32,
+    exposure: f32,
+    gamma: f32
+};
+@group(1) @binding(0) var<uniform> tone_mapping: ToneMappingUniforms;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var color = max(input_color(in.uv).rgb * tone_mapping.exposure, vec3<f32>(0.0));
+    if (tone_mapping.curve == 1u) {
+        color = tonemap_reinhard(color);
+    } else if (tone_mapping.curve == 2u) {
+        color = tonemap_aces(color);
+    } else {
+        color = min(color, vec3<f32>(1.0));
+    }
+    return vec4<f32>(gamma_correct(color, tone_mapping.gamma), 1.0);
+}
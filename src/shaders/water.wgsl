@@ -0,0 +1,95 @@
+// transparent water surface, see `Material::new_water`. Drawn after the opaque meshes without depth buffer,
+// it discards the fragments hidden by them and blends over them with premultiplied alpha
+
+#include "vertex"
+#include "pbr"
+#include "noise"
+#include "depth"
+
+struct WaterUniforms {
+    shallow_color: vec3<f32>,
+    absorption: f32,
+    deep_color: vec3<f32>,
+    roughness: f32,
+    foam_color: vec3<f32>,
+    foam_distance: f32,
+    wave_strength: f32,
+    wavelength: f32,
+    wave_speed: f32
+};
+@group(2) @binding(0) var<uniform> water: WaterUniforms;
+
+// gradient of the height of the waves at `position`, a sum of sine waves travelling in different directions
+// and getting shorter and weaker. Directions are 3D so that waves also cover ocean spheres
+fn wave_gradient(position: vec3<f32>) -> vec3<f32> {
+    var directions = array<vec3<f32>, 4>(
+        vec3<f32>(0.8, 0.1, 0.6),
+        vec3<f32>(-0.5, 0.4, 0.8),
+        vec3<f32>(0.2, -0.7, -0.9),
+        vec3<f32>(-0.9, 0.6, -0.3)
+    );
+    var gradient = vec3<f32>(0.0);
+    var wavelength = water.wavelength;
+    var amplitude = 1.0;
+    var total_amplitude = 0.0;
+    for (var i = 0; i < 4; i = i + 1) {
+        let direction = normalize(directions[i]);
+        let k = 6.28318531 / wavelength;
+        let phase = k * (dot(direction, position) - water.wave_speed * camera.time);
+        gradient = gradient + amplitude * cos(phase) * direction;
+        total_amplitude = total_amplitude + amplitude;
+        wavelength = wavelength * 0.57;
+        amplitude = amplitude * 0.6;
+    }
+    return gradient / total_amplitude;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene_depth = load_depth(in.position);
+    if (in.position.z > scene_depth) {
+        discard;
+    }
+
+    // thickness of water along the view ray, between the surface and the meshes behind it
+    let view_distance = length(in.vWorldPosition - camera.position.xyz);
+    var thickness = 1e4;
+    if (scene_depth < 1.0) {
+        let behind = depth_world_position(screen_uv(in.position), scene_depth);
+        thickness = max(length(behind - camera.position.xyz) - view_distance, 0.0);
+    }
+
+    let up = normalize(in.vWorldNormal);
+    let gradient = wave_gradient(in.vWorldPosition);
+    let n = normalize(up - water.wave_strength * (gradient - up * dot(gradient, up)));
+    let v = normalize(camera.position.xyz - in.vWorldPosition);
+    let l = normalize(lights.sun_direction);
+    let h = normalize(v + l);
+    let n_dot_v = max(dot(n, v), 0.0001);
+    let n_dot_l = max(dot(n, l), 0.0);
+
+    let sun_light = lights.sun_color * lights.sun_intensity * shadow_factor(in.vWorldPosition, up);
+    let fresnel = fresnel_schlick(n_dot_v, vec3<f32>(0.02)).x;
+    let roughness = clamp(water.roughness, 0.04, 1.0);
+    let specular = distribution_ggx(max(dot(n, h), 0.0), roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+        / (4.0 * n_dot_v * n_dot_l + 0.0001) * sun_light * n_dot_l;
+    let sky = ambient_light(reflect(-v, n), roughness);
+
+    // light scattered by the water itself, its color and opacity growing with the thickness
+    let opacity = 1.0 - exp(-water.absorption * thickness);
+    let diffuse_light = sun_light * max(dot(up, l), 0.0) / 3.14159265 + ambient_light(up, 1.0);
+    let body = mix(water.shallow_color, water.deep_color, opacity) * diffuse_light;
+
+    var color = (1.0 - fresnel) * opacity * body + fresnel * sky + specular;
+    var alpha = fresnel + (1.0 - fresnel) * opacity;
+
+    if (water.foam_distance > 0.0) {
+        let shore = clamp(1.0 - thickness / water.foam_distance, 0.0, 1.0);
+        let pattern = value_noise(in.vWorldPosition * 8.0 / water.wavelength + vec3<f32>(0.0, camera.time * water.wave_speed, 0.0));
+        let foam = smoothstep(0.4, 0.7, shore * (0.6 + pattern));
+        color = mix(color, water.foam_color * diffuse_light, foam);
+        alpha = mix(alpha, 1.0, foam);
+    }
+
+    return vec4<f32>(color, alpha);
+}
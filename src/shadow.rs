@@ -0,0 +1,305 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+use bytemuck::{cast_slice, Pod, Zeroable};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4, ortho};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer, PipelineLayout, RenderPipeline, Sampler, TextureView};
+use crate::camera::BasicCamera;
+use crate::engine::{Engine, OPENGL_TO_WGPU_MATRIX};
+use crate::light::DirectionalLight;
+use crate::material::{create_pipeline, PipelineSettings};
+use crate::shader::{ShaderError, ShaderPreprocessor};
+use crate::uniforms::CameraUniforms;
+use crate::vertex::{instance_buffer_layout, VertexAttribute, VertexBufferLayouts};
+
+pub const MAX_CASCADES: usize = 4;
+pub const SHADOW_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Vertex attributes read by the shadow map pass.
+pub const SHADOW_ATTRIBUTES: [VertexAttribute; 1] = [VertexAttribute::Position];
+
+/// Parameters of the cascaded shadow maps of the sun, disabled by default, see `Scene::set_shadow_settings`.
+#[derive(Debug, Copy, Clone)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    /// width and height in texels of each cascade
+    pub resolution: u32,
+    /// 1 to `MAX_CASCADES` shadow maps, each covering a farther slice of the view frustum
+    pub cascade_count: u32,
+    /// distance from the camera up to which shadows are drawn
+    pub max_distance: f32,
+    /// blend between uniform (0) and logarithmic (1) splits of the view frustum
+    pub split_lambda: f32,
+    /// distance beyond the view frustum, towards the sun, in which meshes still cast shadows
+    pub caster_distance: f32,
+    /// depth bias of the shadow pass, in depth buffer units and scaled by the slope of the triangles
+    pub depth_bias: i32,
+    pub slope_bias: f32,
+    /// offset of the shaded point along its normal, in shadow map texels
+    pub normal_bias: f32,
+    /// the PCF kernel is (2 * pcf_radius + 1)² samples
+    pub pcf_radius: i32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            enabled: false,
+            resolution: 2048,
+            cascade_count: 3,
+            max_distance: 60.0,
+            split_lambda: 0.6,
+            caster_distance: 50.0,
+            depth_bias: 2,
+            slope_bias: 2.0,
+            normal_bias: 1.5,
+            pcf_radius: 1,
+        }
+    }
+}
+
+/// Uniforms of the shadow maps, bound at group 0, binding 4, with the shadow maps at binding 5 and their sampler at binding 6.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ShadowUniforms {
+    pub view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    /// view depth at which each cascade ends
+    pub split_depths: [f32; MAX_CASCADES],
+    /// world size of a texel of each cascade
+    pub texel_sizes: [f32; MAX_CASCADES],
+    pub camera_forward: [f32; 3],
+    /// 0 when shadows are disabled
+    pub cascade_count: u32,
+    pub normal_bias: f32,
+    pub pcf_radius: i32,
+    pub _padding: [f32; 2],
+}
+
+/// Cascaded shadow maps of the sun, rendered in a depth pass over the meshes before the main pass.
+/// When disabled it only holds 1x1 placeholders so the scene bind group stays valid.
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    pub texture: wgpu::Texture,
+    /// all the cascades, sampled by the materials
+    pub view: TextureView,
+    /// one view per cascade, rendered by the shadow pass
+    pub cascade_views: Vec<TextureView>,
+    pub sampler: Sampler,
+    pub uniform_buffer: Buffer,
+    /// camera uniforms of each cascade, bound by the shadow pass in place of the camera of the scene
+    pub cascade_camera_buffers: Vec<Buffer>,
+    /// set by the scene, see `Scene::set_shadow_settings`
+    pub(crate) cascade_bind_groups: Vec<BindGroup>,
+    /// empty shadow map bound by the shadow pass, which cannot sample the texture it renders to
+    pub placeholder_view: TextureView,
+    pipeline_layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    /// draws the instanced meshes, reading their instance buffer after the positions
+    instanced_pipeline: RenderPipeline,
+}
+
+impl ShadowMap {
+    pub fn new(settings: ShadowSettings, engine: &Engine) -> ShadowMap {
+        if settings.enabled {
+            assert!((1..=MAX_CASCADES as u32).contains(&settings.cascade_count), "Shadows need 1 to {} cascades, not {}", MAX_CASCADES, settings.cascade_count);
+        }
+
+        let (resolution, layers) = if settings.enabled { (settings.resolution, settings.cascade_count) } else { (1, 1) };
+        let texture = create_depth_array(resolution, layers, "Shadow Map", engine);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..layers).map(|layer| texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: NonZeroU32::new(1),
+            ..Default::default()
+        })).collect();
+        let placeholder_view = create_depth_array(1, 1, "Shadow Map Placeholder", engine).create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = engine.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Map Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            contents: cast_slice(&[ShadowUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let cascade_camera_buffers = (0..layers).map(|_| engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Cascade Camera Buffer"),
+            contents: cast_slice(&[CameraUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })).collect();
+
+        let pipeline_layout = engine.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&engine.layouts.scene, &engine.layouts.mesh],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_shadow_pipeline(&pipeline_layout, &settings, false, engine)
+            .unwrap_or_else(|e| panic!("Could not create shader \"shadow\": {}", e));
+        let instanced_pipeline = create_shadow_pipeline(&pipeline_layout, &settings, true, engine)
+            .unwrap_or_else(|e| panic!("Could not create shader \"shadow\": {}", e));
+
+        ShadowMap {
+            settings,
+            texture,
+            view,
+            cascade_views,
+            sampler,
+            uniform_buffer,
+            cascade_camera_buffers,
+            cascade_bind_groups: Vec::new(),
+            placeholder_view,
+            pipeline_layout,
+            pipeline,
+            instanced_pipeline,
+        }
+    }
+
+    pub fn pipeline(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn instanced_pipeline(&self) -> &RenderPipeline {
+        &self.instanced_pipeline
+    }
+
+    /// Rebuilds the pipeline of the shadow pass, keeping the previous one on errors.
+    pub fn recompile(&mut self, engine: &Engine) -> Result<(), ShaderError> {
+        self.pipeline = create_shadow_pipeline(&self.pipeline_layout, &self.settings, false, engine)?;
+        self.instanced_pipeline = create_shadow_pipeline(&self.pipeline_layout, &self.settings, true, engine)?;
+        Ok(())
+    }
+
+    /// Fits the cascades to the view frustum of `camera` and uploads their matrices.
+    pub fn update(&self, camera: &BasicCamera, sun: &DirectionalLight, engine: &Engine) {
+        let mut uniforms = ShadowUniforms::zeroed();
+        if !self.settings.enabled {
+            engine.queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniforms]));
+            return;
+        }
+
+        let view = camera.get_view_matrix();
+        let camera_forward = -Vector3::new(view.x.z, view.y.z, view.z.z);
+        let inverse_view_proj = (camera.get_projection_matrix() * view).invert().unwrap_or_else(Matrix4::identity);
+
+        // rays from the near to the far corners of the view frustum
+        let unproject = |x: f32, y: f32, z: f32| {
+            let point = inverse_view_proj * Vector4::new(x, y, z, 1.0);
+            point.truncate() / point.w
+        };
+        let corner_rays: Vec<(Vector3<f32>, Vector3<f32>)> = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].iter()
+            .map(|&(x, y)| (unproject(x, y, 0.0), unproject(x, y, 1.0)))
+            .collect();
+
+        let near = camera.z_near;
+        let far = camera.z_far.min(self.settings.max_distance);
+        let splits = cascade_splits(near, far, self.settings.cascade_count, self.settings.split_lambda);
+
+        let light_direction = sun.direction.normalize();
+        let up = if light_direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+
+        let mut slice_near = near;
+        for (cascade, &slice_far) in splits.iter().enumerate() {
+            // corners of the slice, interpolated along the rays of the frustum by view depth
+            let corners: Vec<Vector3<f32>> = corner_rays.iter().flat_map(|(ray_near, ray_far)| {
+                [slice_near, slice_far].map(|depth| ray_near + (ray_far - ray_near) * ((depth - camera.z_near) / (camera.z_far - camera.z_near)))
+            }).collect();
+
+            // bounding sphere of the slice, so the cascade does not change size when the camera rotates
+            let center = corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, corner| sum + corner) / corners.len() as f32;
+            let radius = corners.iter().map(|corner| (corner - center).magnitude()).fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            let eye = center + light_direction * (radius + self.settings.caster_distance);
+            let light_view = Matrix4::look_at_rh(Point3::from_vec(eye), Point3::from_vec(center), up);
+            let mut light_proj = OPENGL_TO_WGPU_MATRIX * ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + self.settings.caster_distance);
+
+            // snap the cascade to whole texels so shadows do not shimmer when the camera moves
+            let half_resolution = self.settings.resolution as f32 / 2.0;
+            let origin = (light_proj * light_view) * Vector4::new(0.0, 0.0, 0.0, 1.0);
+            let (origin_x, origin_y) = (origin.x * half_resolution, origin.y * half_resolution);
+            light_proj.w.x += (origin_x.round() - origin_x) / half_resolution;
+            light_proj.w.y += (origin_y.round() - origin_y) / half_resolution;
+
+            let light_view_proj = light_proj * light_view;
+            uniforms.view_proj[cascade] = light_view_proj.into();
+            uniforms.split_depths[cascade] = slice_far;
+            uniforms.texel_sizes[cascade] = 2.0 * radius / self.settings.resolution as f32;
+
+            let cascade_camera = CameraUniforms::new(light_view_proj, eye, 0.0, 2.0 * radius + self.settings.caster_distance, 0.0);
+            engine.queue.write_buffer(&self.cascade_camera_buffers[cascade], 0, cast_slice(&[cascade_camera]));
+
+            slice_near = slice_far;
+        }
+
+        uniforms.camera_forward = camera_forward.into();
+        uniforms.cascade_count = self.settings.cascade_count;
+        uniforms.normal_bias = self.settings.normal_bias;
+        uniforms.pcf_radius = self.settings.pcf_radius;
+        engine.queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniforms]));
+    }
+}
+
+/// View depths at which the cascades end, blending uniform and logarithmic splits by `lambda`.
+pub fn cascade_splits(near: f32, far: f32, cascade_count: u32, lambda: f32) -> Vec<f32> {
+    (1..=cascade_count).map(|i| {
+        let fraction = i as f32 / cascade_count as f32;
+        let logarithmic = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+        lambda * logarithmic + (1.0 - lambda) * uniform
+    }).collect()
+}
+
+fn create_depth_array(resolution: u32, layers: u32, label: &str, engine: &Engine) -> wgpu::Texture {
+    engine.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: SHADOW_MAP_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    })
+}
+
+fn create_shadow_pipeline(pipeline_layout: &PipelineLayout, settings: &ShadowSettings, instanced: bool, engine: &Engine) -> Result<RenderPipeline, ShaderError> {
+    let mut preprocessor = ShaderPreprocessor::new();
+    preprocessor.shader_dir = engine.shader_dir().map(Path::to_path_buf);
+    let vertex_layouts = VertexBufferLayouts::new(&SHADOW_ATTRIBUTES);
+    let mut vertex_buffers = vertex_layouts.desc();
+    if instanced {
+        preprocessor.define("INSTANCED", "");
+        vertex_buffers.push(instance_buffer_layout());
+    }
+    let pipeline_settings = PipelineSettings {
+        depth_bias: wgpu::DepthBiasState {
+            constant: settings.depth_bias,
+            slope_scale: settings.slope_bias,
+            clamp: 0.0,
+        },
+        depth_format: Some(SHADOW_MAP_FORMAT),
+        depth_only: true,
+        multisampled: false,
+        ..Default::default()
+    };
+    let (_, pipeline) = create_pipeline("shadow", &preprocessor, pipeline_layout, &vertex_buffers, &pipeline_settings, engine)?;
+    Ok(pipeline)
+}
@@ -0,0 +1,440 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+use cgmath::{InnerSpace, Vector3};
+use half::f16;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::{DynamicImage, ImageError, Rgb32FImage, RgbaImage};
+use image::imageops::FilterType;
+use wgpu::{Sampler, TextureView};
+use crate::engine::Engine;
+
+/// How a texture is uploaded and sampled.
+#[derive(Debug, Copy, Clone)]
+pub struct TextureOptions {
+    /// whether the texels are colors in sRGB space, false for data such as normal maps
+    pub srgb: bool,
+    pub generate_mipmaps: bool,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub address_mode: wgpu::AddressMode,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            srgb: true,
+            generate_mipmaps: true,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            address_mode: wgpu::AddressMode::Repeat,
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Options for textures holding data rather than colors, such as normal maps.
+    pub fn linear() -> TextureOptions {
+        TextureOptions {
+            srgb: false,
+            ..Default::default()
+        }
+    }
+}
+
+/// A 2D RGBA texture with its view and sampler, ready to be bound by materials.
+pub struct Texture {
+    pub texture: wgpu::Texture,
+    pub view: TextureView,
+    pub sampler: Sampler,
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+    pub mip_level_count: u32,
+}
+
+impl Texture {
+    /// Loads a PNG or JPEG image from disk.
+    pub fn from_path(path: impl AsRef<Path>, options: TextureOptions, engine: &Engine) -> Result<Texture, ImageError> {
+        let image = image::open(path)?;
+        Ok(Texture::from_image(&image, options, engine))
+    }
+
+    /// Decodes a PNG or JPEG image from memory, for instance from `include_bytes!`.
+    pub fn from_bytes(bytes: &[u8], options: TextureOptions, engine: &Engine) -> Result<Texture, ImageError> {
+        let image = image::load_from_memory(bytes)?;
+        Ok(Texture::from_image(&image, options, engine))
+    }
+
+    pub fn from_image(image: &DynamicImage, options: TextureOptions, engine: &Engine) -> Texture {
+        Texture::from_rgba8(image.to_rgba8(), options, engine)
+    }
+
+    /// Creates a texture from raw RGBA pixels, generating the mip chain on the CPU if requested.
+    pub fn from_rgba8(image: RgbaImage, options: TextureOptions, engine: &Engine) -> Texture {
+        let (width, height) = image.dimensions();
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let mip_level_count = if options.generate_mipmaps { mip_level_count(width, height) } else { 1 };
+        let format = if options.srgb { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm };
+
+        let texture = engine.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture"),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        let mut level_image = image;
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let (level_width, level_height) = level_image.dimensions();
+                level_image = image::imageops::resize(&level_image, (level_width / 2).max(1), (level_height / 2).max(1), FilterType::Triangle);
+            }
+            write_rgba8(&texture, mip_level, &level_image, engine);
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = engine.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: options.address_mode,
+            address_mode_v: options.address_mode,
+            address_mode_w: options.address_mode,
+            mag_filter: options.mag_filter,
+            min_filter: options.min_filter,
+            mipmap_filter: options.mipmap_filter,
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            sampler,
+            size,
+            format,
+            mip_level_count,
+        }
+    }
+
+    /// Creates an empty texture that render passes draw into, such as the one of a `RenderTexture`,
+    /// sampled linearly and clamped to its edges.
+    pub fn new_render_attachment(width: u32, height: u32, format: wgpu::TextureFormat, label: &str, engine: &Engine) -> Texture {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = engine.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = engine.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Render Attachment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Texture {
+            texture,
+            view,
+            sampler,
+            size,
+            format,
+            mip_level_count: 1,
+        }
+    }
+
+    /// Bakes the object space normals of a terrain made by `Mesh::new_procedural_terrain` with the same
+    /// `size`, `nb_subdivisions` and `height_fn` into a `resolution` x `resolution` normal map,
+    /// to be used with `NormalMapSpace::Object` so lighting keeps details finer than the mesh.
+    pub fn new_terrain_normal_map(size: f32, nb_subdivisions: u32, height_fn: &dyn Fn(f32, f32) -> f32, resolution: u32, engine: &Engine) -> Texture {
+        // inverse of the uv mapping of the terrain vertices
+        let uv_to_world = |uv: f32| (uv * (nb_subdivisions - 1) as f32 - nb_subdivisions as f32 / 2.0) * size / nb_subdivisions as f32;
+        let texel_size = (nb_subdivisions - 1) as f32 / resolution as f32 * size / nb_subdivisions as f32;
+
+        let image = RgbaImage::from_fn(resolution, resolution, |i, j| {
+            let x = uv_to_world((i as f32 + 0.5) / resolution as f32);
+            let z = uv_to_world((j as f32 + 0.5) / resolution as f32);
+
+            let dh_dx = (height_fn(x + texel_size, z) - height_fn(x - texel_size, z)) / (2.0 * texel_size);
+            let dh_dz = (height_fn(x, z + texel_size) - height_fn(x, z - texel_size)) / (2.0 * texel_size);
+            let normal = Vector3::new(-dh_dx, 1.0, -dh_dz).normalize();
+
+            let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+            image::Rgba([encode(normal.x), encode(normal.y), encode(normal.z), 255])
+        });
+
+        Texture::from_rgba8(image, TextureOptions {
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            ..TextureOptions::linear()
+        }, engine)
+    }
+}
+
+/// A cube texture, such as a skybox. Faces are in the +X, -X, +Y, -Y, +Z, -Z order.
+pub struct Cubemap {
+    pub texture: wgpu::Texture,
+    /// view of the 6 faces as a cube
+    pub view: TextureView,
+    pub sampler: Sampler,
+    /// width and height of each face
+    pub size: u32,
+    pub format: wgpu::TextureFormat,
+    /// linear average of the texels, for instance to tint the fog like the sky
+    pub average_color: [f32; 3],
+}
+
+impl Cubemap {
+    /// Loads the 6 faces of the cube from PNG or JPEG images of the same square size, in the +X, -X, +Y, -Y, +Z, -Z order.
+    pub fn from_paths<P: AsRef<Path>>(paths: [P; 6], engine: &Engine) -> Result<Cubemap, ImageError> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            faces.push(image::open(path)?.to_rgba8());
+        }
+        let size = faces[0].width();
+        if faces.iter().any(|face| face.dimensions() != (size, size)) {
+            return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+        }
+
+        let mut sum = [0.0; 3];
+        for pixel in faces.iter().flat_map(|face| face.pixels()) {
+            for (total, &channel) in sum.iter_mut().zip(pixel.0.iter()) {
+                *total += srgb_to_linear(channel as f32 / 255.0);
+            }
+        }
+        let texel_count = (6 * size * size) as f32;
+        let average_color = sum.map(|total| total / texel_count);
+
+        let data: Vec<u8> = faces.iter().flat_map(|face| face.as_raw().iter().copied()).collect();
+        Ok(Cubemap::from_data(&data, size, wgpu::TextureFormat::Rgba8UnormSrgb, 4, average_color, engine))
+    }
+
+    /// Loads an equirectangular image, typically a Radiance HDR panorama, and projects it on the faces of a cube
+    /// of `face_size` texels, keeping the colors over 1.
+    pub fn from_equirectangular_path(path: impl AsRef<Path>, face_size: u32, engine: &Engine) -> Result<Cubemap, ImageError> {
+        let image = image::open(path)?.to_rgb32f();
+        Ok(Cubemap::from_equirectangular(&image, face_size, engine))
+    }
+
+    pub fn from_equirectangular(image: &Rgb32FImage, face_size: u32, engine: &Engine) -> Cubemap {
+        let mut data = Vec::with_capacity((6 * face_size * face_size * 8) as usize);
+        let mut sum = [0.0; 3];
+        for face in 0..6 {
+            for y in 0..face_size {
+                for x in 0..face_size {
+                    let u = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+                    let v = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+                    let color = sample_equirectangular(image, cube_face_direction(face, u, v));
+                    for (total, channel) in sum.iter_mut().zip(color) {
+                        *total += channel;
+                    }
+                    for channel in [color[0], color[1], color[2], 1.0] {
+                        data.extend_from_slice(&f32_to_f16(channel).to_le_bytes());
+                    }
+                }
+            }
+        }
+        let texel_count = (6 * face_size * face_size) as f32;
+        let average_color = sum.map(|total| total / texel_count);
+        Cubemap::from_data(&data, face_size, wgpu::TextureFormat::Rgba16Float, 8, average_color, engine)
+    }
+
+    /// A cube of a single color, bound when there is no skybox.
+    pub fn from_color(color: [f32; 3], engine: &Engine) -> Cubemap {
+        let texel: Vec<u8> = [color[0], color[1], color[2], 1.0].iter().flat_map(|&channel| f32_to_f16(channel).to_le_bytes()).collect();
+        Cubemap::from_data(&texel.repeat(6), 1, wgpu::TextureFormat::Rgba16Float, 8, color, engine)
+    }
+
+    fn from_data(data: &[u8], size: u32, format: wgpu::TextureFormat, bytes_per_texel: u32, average_color: [f32; 3], engine: &Engine) -> Cubemap {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        };
+        let texture = engine.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Cubemap"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        engine.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(bytes_per_texel * size),
+                rows_per_image: NonZeroU32::new(size),
+            },
+            extent,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = engine.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Cubemap Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Cubemap {
+            texture,
+            view,
+            sampler,
+            size,
+            format,
+            average_color,
+        }
+    }
+}
+
+/// Direction of the texel at `u`, `v` in [-1, 1] of a face of a cube, `v` going down.
+fn cube_face_direction(face: u32, u: f32, v: f32) -> [f32; 3] {
+    let [x, y, z] = match face {
+        0 => [1.0, -v, -u],
+        1 => [-1.0, -v, u],
+        2 => [u, 1.0, v],
+        3 => [u, -1.0, -v],
+        4 => [u, -v, 1.0],
+        _ => [-u, -v, -1.0],
+    };
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
+/// Bilinear sample of an equirectangular image in `direction`, with the mapping of the environment maps.
+fn sample_equirectangular(image: &Rgb32FImage, direction: [f32; 3]) -> [f32; 3] {
+    let (width, height) = image.dimensions();
+    let u = direction[2].atan2(direction[0]) / std::f32::consts::TAU + 0.5;
+    let v = direction[1].clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+    let x = u * width as f32 - 0.5;
+    let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    // wraps horizontally around the panorama
+    let texel = |x: f32, y: f32| image.get_pixel((x as i64).rem_euclid(width as i64) as u32, (y as u32).min(height - 1)).0;
+    let (a, b, c, d) = (texel(x0, y0), texel(x0 + 1.0, y0), texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+    [0, 1, 2].map(|i| {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        top + (bottom - top) * fy
+    })
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Converts to a half precision float, rounding to the nearest and clamping to the largest finite half,
+/// as infinite texels would spread over their neighbors when filtered.
+fn f32_to_f16(value: f32) -> u16 {
+    f16::from_f32(value.clamp(f16::MIN.to_f32(), f16::MAX.to_f32())).to_bits()
+}
+
+/// Number of levels of a full mip chain, down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+fn write_rgba8(texture: &wgpu::Texture, mip_level: u32, image: &RgbaImage, engine: &Engine) {
+    let (width, height) = image.dimensions();
+    engine.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        image.as_raw(),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(4 * width),
+            rows_per_image: NonZeroU32::new(height),
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, Rgb32FImage};
+    use super::{f32_to_f16, sample_equirectangular};
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        assert!(actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 1e-4), "{:?} != {:?}", actual, expected);
+    }
+
+    /// 4 x 2 panorama whose texels hold the square of their column and their row.
+    fn panorama() -> Rgb32FImage {
+        Rgb32FImage::from_fn(4, 2, |x, y| Rgb([(x * x) as f32, y as f32, 0.0]))
+    }
+
+    #[test]
+    fn equirectangular_wraps_horizontally() {
+        // -X is on the right edge of the panorama, between the last and the first column
+        assert_near(sample_equirectangular(&panorama(), [-1.0, 0.0, 0.0]), [4.5, 0.5, 0.0]);
+        assert_near(sample_equirectangular(&panorama(), [1.0, 0.0, 0.0]), [2.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn equirectangular_clamps_at_the_poles() {
+        assert_near(sample_equirectangular(&panorama(), [0.0, 1.0, 0.0]), [2.5, 0.0, 0.0]);
+        assert_near(sample_equirectangular(&panorama(), [0.0, -1.0, 0.0]), [2.5, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn f16_rounds_to_nearest_even() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        // halfway between 1 and the next half
+        assert_eq!(f32_to_f16(1.0 + f32::powi(2.0, -11)), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * f32::powi(2.0, -11)), 0x3c02);
+    }
+
+    #[test]
+    fn f16_keeps_denormals() {
+        assert_eq!(f32_to_f16(f32::powi(2.0, -24)), 0x0001);
+        assert_eq!(f32_to_f16(f32::powi(2.0, -15)), 0x0200);
+        assert_eq!(f32_to_f16(f32::powi(2.0, -26)), 0x0000);
+    }
+
+    #[test]
+    fn f16_clamps_infinity_to_the_largest_half() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7bff);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfbff);
+        assert_eq!(f32_to_f16(1.0e6), 0x7bff);
+        assert!(f32_to_f16(f32::NAN) & 0x7c00 == 0x7c00 && f32_to_f16(f32::NAN) & 0x03ff != 0);
+    }
+}
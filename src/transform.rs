@@ -1,6 +1,6 @@
 use cgmath::{InnerSpace, Matrix4, Point3, Rad, Vector3, Vector4};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transform {
     pub position: Vector3<f32>,
     pub rotation: Point3<f32>,
@@ -0,0 +1,197 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use wgpu::{BindGroupLayout, Device};
+use crate::camera::BasicCamera;
+
+/// Uniforms bound at group 0, binding 0 of every pipeline, written once per frame by the scene.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct CameraUniforms {
+    pub view_proj: [[f32; 4]; 4],
+    /// turns clip space positions back into world space, for passes reading the depth buffer
+    pub inverse_view_proj: [[f32; 4]; 4],
+    pub position: [f32; 4],
+    pub near: f32,
+    pub far: f32,
+    /// seconds since the scene was created, for animated materials
+    pub time: f32,
+    pub _padding: f32,
+}
+
+impl CameraUniforms {
+    pub fn new(view_proj: Matrix4<f32>, position: Vector3<f32>, near: f32, far: f32, time: f32) -> CameraUniforms {
+        CameraUniforms {
+            view_proj: view_proj.into(),
+            inverse_view_proj: view_proj.invert().unwrap_or_else(Matrix4::identity).into(),
+            position: [position.x, position.y, position.z, 1.0],
+            near,
+            far,
+            time,
+            _padding: 0.0,
+        }
+    }
+
+    pub fn from_camera(camera: &BasicCamera, time: f32) -> CameraUniforms {
+        let view_proj = camera.get_projection_matrix() * camera.get_view_matrix();
+        CameraUniforms::new(view_proj, camera.transform.position, camera.z_near, camera.z_far, time)
+    }
+}
+
+/// Uniforms bound at group 1, one buffer per mesh.
+/// The bounds are the object space bounding box of the mesh, used by debug render modes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct MeshUniforms {
+    pub world: [[f32; 4]; 4],
+    pub normal_matrix: [[f32; 4]; 4],
+    pub bounds_min: [f32; 4],
+    pub bounds_max: [f32; 4],
+}
+
+impl MeshUniforms {
+    pub fn new(world: Matrix4<f32>, bounds_min: [f32; 3], bounds_max: [f32; 3]) -> MeshUniforms {
+        let normal_matrix = world.invert().unwrap_or_else(Matrix4::identity);
+        MeshUniforms {
+            world: world.into(),
+            // transpose of the inverse, written column major
+            normal_matrix: [
+                [normal_matrix.x.x, normal_matrix.y.x, normal_matrix.z.x, 0.0],
+                [normal_matrix.x.y, normal_matrix.y.y, normal_matrix.z.y, 0.0],
+                [normal_matrix.x.z, normal_matrix.y.z, normal_matrix.z.z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            bounds_min: [bounds_min[0], bounds_min[1], bounds_min[2], 1.0],
+            bounds_max: [bounds_max[0], bounds_max[1], bounds_max[2], 1.0],
+        }
+    }
+}
+
+/// Bind group layouts shared by every pipeline of the engine.
+pub struct BindGroupLayouts {
+    /// camera, lights, environment map and shadow maps, see `scene_bind_group_layout`
+    pub scene: BindGroupLayout,
+    pub mesh: BindGroupLayout,
+    /// group 3 of materials reading the depth buffer of the opaque meshes, see `Material::reads_scene_depth`
+    pub scene_depth: BindGroupLayout,
+}
+
+impl BindGroupLayouts {
+    pub fn new(device: &Device) -> BindGroupLayouts {
+        BindGroupLayouts {
+            scene: scene_bind_group_layout(device),
+            mesh: uniform_bind_group_layout(device, wgpu::ShaderStages::VERTEX_FRAGMENT, "Mesh Bind Group Layout"),
+            scene_depth: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[depth_texture_entry(0)],
+                label: Some("Scene Depth Bind Group Layout"),
+            }),
+        }
+    }
+}
+
+/// Layout of a bind group made of a single uniform buffer at binding 0.
+pub fn uniform_bind_group_layout(device: &Device, visibility: wgpu::ShaderStages, label: &str) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+        label: Some(label),
+    })
+}
+
+/// Layout of the group 1 of fullscreen passes reading the depth buffer of the main pass: a uniform buffer at binding 0
+/// and the depth texture at binding 1.
+pub fn depth_pass_bind_group_layout(device: &Device, label: &str) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            depth_texture_entry(1),
+        ],
+        label: Some(label),
+    })
+}
+
+/// Depth buffer read in a fragment shader, declared as an unfilterable float texture since the GL backend cannot load from depth textures.
+pub(crate) fn depth_texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+/// Layout of the group 0 shared by every pipeline: `CameraUniforms` at binding 0, `LightUniforms` at binding 1,
+/// the environment map texture and sampler at bindings 2 and 3, `ShadowUniforms` at binding 4
+/// and the shadow maps with their comparison sampler at bindings 5 and 6.
+fn scene_bind_group_layout(device: &Device) -> BindGroupLayout {
+    let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            uniform_entry(0),
+            uniform_entry(1),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            uniform_entry(4),
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+        ],
+        label: Some("Scene Bind Group Layout"),
+    })
+}
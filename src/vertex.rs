@@ -0,0 +1,117 @@
+/// An attribute of the vertices of a mesh. Each attribute is stored tightly packed in its own vertex buffer
+/// and read by shaders at a fixed location, so a material works with any mesh providing the attributes it reads.
+/// Instanced meshes read their instances at the locations 11 to 15, so their materials cannot read custom attributes 3 to 7.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum VertexAttribute {
+    /// `vec3<f32>` at location 0
+    Position,
+    /// `vec3<f32>` at location 1 for the first channel, `vec4<f32>` at locations 5 to 7 for the channels 1 to 3
+    Color(u32),
+    /// `vec3<f32>` at location 2
+    Normal,
+    /// `vec2<f32>` at location 3
+    Uv,
+    /// `vec4<f32>` at location 4, w being the handedness of the bitangent
+    Tangent,
+    /// custom attribute of index 0 to 7 made of 1 to 4 floats, at location 8 + index
+    Custom { index: u32, components: u32 },
+}
+
+pub const MAX_COLOR_CHANNELS: u32 = 4;
+pub const MAX_CUSTOM_ATTRIBUTES: u32 = 8;
+/// first location of the per-instance data of instanced meshes, see `instance_buffer_layout`
+pub const INSTANCE_LOCATION: u32 = 11;
+
+const INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+    11 => Float32x4,
+    12 => Float32x4,
+    13 => Float32x4,
+    14 => Float32x4,
+    15 => Float32x4,
+];
+
+impl VertexAttribute {
+    pub fn location(self) -> u32 {
+        match self {
+            VertexAttribute::Position => 0,
+            VertexAttribute::Color(0) => 1,
+            VertexAttribute::Normal => 2,
+            VertexAttribute::Uv => 3,
+            VertexAttribute::Tangent => 4,
+            VertexAttribute::Color(channel) => {
+                assert!(channel < MAX_COLOR_CHANNELS, "Color channel {} is out of range", channel);
+                4 + channel
+            }
+            VertexAttribute::Custom { index, .. } => {
+                assert!(index < MAX_CUSTOM_ATTRIBUTES, "Custom attribute {} is out of range", index);
+                8 + index
+            }
+        }
+    }
+
+    pub fn format(self) -> wgpu::VertexFormat {
+        match self {
+            VertexAttribute::Position | VertexAttribute::Normal | VertexAttribute::Color(0) => wgpu::VertexFormat::Float32x3,
+            VertexAttribute::Color(_) | VertexAttribute::Tangent => wgpu::VertexFormat::Float32x4,
+            VertexAttribute::Uv => wgpu::VertexFormat::Float32x2,
+            VertexAttribute::Custom { components: 1, .. } => wgpu::VertexFormat::Float32,
+            VertexAttribute::Custom { components: 2, .. } => wgpu::VertexFormat::Float32x2,
+            VertexAttribute::Custom { components: 3, .. } => wgpu::VertexFormat::Float32x3,
+            VertexAttribute::Custom { components: 4, .. } => wgpu::VertexFormat::Float32x4,
+            VertexAttribute::Custom { components, .. } => panic!("Custom attributes have 1 to 4 components, not {}", components),
+        }
+    }
+
+    /// Size in bytes of the attribute of one vertex.
+    pub fn size(self) -> wgpu::BufferAddress {
+        self.format().size()
+    }
+}
+
+/// Per-vertex data of a custom attribute, `components` floats per vertex.
+#[derive(Debug, Clone)]
+pub struct CustomAttribute {
+    pub components: u32,
+    pub data: Vec<f32>,
+}
+
+/// Vertex buffer layouts of a list of attributes, the i-th attribute being read from the vertex buffer in slot i.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VertexBufferLayouts {
+    pub attributes: Vec<VertexAttribute>,
+    wgpu_attributes: Vec<[wgpu::VertexAttribute; 1]>,
+}
+
+impl VertexBufferLayouts {
+    pub fn new(attributes: &[VertexAttribute]) -> VertexBufferLayouts {
+        VertexBufferLayouts {
+            attributes: attributes.to_vec(),
+            wgpu_attributes: attributes.iter().map(|attribute| [wgpu::VertexAttribute {
+                format: attribute.format(),
+                offset: 0,
+                shader_location: attribute.location(),
+            }]).collect(),
+        }
+    }
+
+    pub fn desc(&self) -> Vec<wgpu::VertexBufferLayout<'_>> {
+        self.attributes.iter().zip(self.wgpu_attributes.iter()).map(|(attribute, wgpu_attributes)| wgpu::VertexBufferLayout {
+            array_stride: attribute.size(),
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: wgpu_attributes,
+        }).collect()
+    }
+}
+
+/// Layout of the instance buffer of instanced meshes, bound after the vertex buffers: the columns of the world matrix
+/// of each instance at locations 11 to 14 and its color at location 15, see `InstanceData`.
+pub fn instance_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<[[f32; 4]; 5]>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &INSTANCE_ATTRIBUTES,
+    }
+}
+
+/// The attributes read by the built-in shaders through the `vertex` chunk.
+pub const BASE_ATTRIBUTES: [VertexAttribute; 3] = [VertexAttribute::Position, VertexAttribute::Color(0), VertexAttribute::Normal];
@@ -0,0 +1,82 @@
+use bytemuck::{cast_slice, Zeroable};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroup, Buffer};
+use winit::dpi::PhysicalPosition;
+use crate::camera::BasicCamera;
+use crate::engine::Engine;
+use crate::render_mode::RenderMode;
+use crate::render_target::RenderTargets;
+use crate::uniforms::CameraUniforms;
+
+/// A region of the window showing the scene through its own camera, for side by side views. See `Scene::add_viewport`.
+/// Each viewport is rendered and post-processed at its own size, then copied into its region of the window
+/// with the debug drawings, whose texts face the active camera of the scene.
+/// Input is given to the camera of the viewport under the cursor.
+pub struct Viewport {
+    /// left, top, width and height as fractions of the window
+    pub rect: [f32; 4],
+    /// None to show the active camera of the scene, drawn with the aspect ratio of the viewport
+    pub camera: Option<BasicCamera>,
+    /// bit mask of the layers of the meshes drawn, see `Mesh::layers`
+    pub layers: u32,
+    /// render mode of the meshes without their own, instead of the one of the scene
+    pub render_mode: Option<RenderMode>,
+    /// intermediate buffers of the passes, at the size of the viewport
+    pub(crate) targets: RenderTargets,
+    pub(crate) camera_uniform_buffer: Buffer,
+    /// group 0 seeing the scene through the camera of the viewport, created by `Scene::update`
+    /// and dropped when the scene bind groups change
+    pub(crate) scene_bind_group: Option<BindGroup>,
+}
+
+impl Viewport {
+    /// Creates a viewport over `rect` of the window, drawing every layer with the render mode of the scene.
+    pub fn new(rect: [f32; 4], camera: Option<BasicCamera>, engine: &Engine) -> Viewport {
+        let camera_uniform_buffer = engine.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Viewport Camera Uniform Buffer"),
+            contents: cast_slice(&[CameraUniforms::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Viewport {
+            rect,
+            camera,
+            layers: u32::MAX,
+            render_mode: None,
+            // sized by `update` from the window of the scene
+            targets: RenderTargets::new(&engine.device, &engine.layouts, 1, 1, engine.sample_count()),
+            camera_uniform_buffer,
+            scene_bind_group: None,
+        }
+    }
+
+    /// Left, top, width and height in pixels of the region of a `width` x `height` window,
+    /// at least one pixel large and clamped to the window.
+    pub fn pixel_rect(&self, width: u32, height: u32) -> [u32; 4] {
+        let to_pixels = |fraction: f32, size: u32| ((fraction * size as f32).round().max(0.0) as u32).min(size);
+        let left = to_pixels(self.rect[0], width).min(width.saturating_sub(1));
+        let top = to_pixels(self.rect[1], height).min(height.saturating_sub(1));
+        let right = to_pixels(self.rect[0] + self.rect[2], width).max(left + 1);
+        let bottom = to_pixels(self.rect[1] + self.rect[3], height).max(top + 1);
+        [left, top, right - left, bottom - top]
+    }
+
+    /// Whether `position`, in pixels of a `width` x `height` window, is in the viewport.
+    pub fn contains(&self, position: PhysicalPosition<f64>, width: u32, height: u32) -> bool {
+        let [left, top, rect_width, rect_height] = self.pixel_rect(width, height);
+        position.x >= left as f64 && position.x < (left + rect_width) as f64
+            && position.y >= top as f64 && position.y < (top + rect_height) as f64
+    }
+
+    /// Follows the size of the `window_width` x `window_height` window, and uploads the camera.
+    pub(crate) fn update(&mut self, active_camera: &BasicCamera, window_width: u32, window_height: u32, time: f32, engine: &Engine) {
+        let [_, _, width, height] = self.pixel_rect(window_width, window_height);
+        self.targets.resize(&engine.device, &engine.layouts, width, height, engine.sample_count());
+
+        // a copy, so the active camera keeps the aspect ratio of the window
+        let mut camera = self.camera.as_ref().unwrap_or(active_camera).clone();
+        camera.aspect_ratio = width as f32 / height as f32;
+        let camera_uniforms = CameraUniforms::from_camera(&camera, time);
+        engine.queue.write_buffer(&self.camera_uniform_buffer, 0, cast_slice(&[camera_uniforms]));
+    }
+}